@@ -0,0 +1,522 @@
+use std::collections::HashSet;
+use std::fmt;
+use std::io::{self, Write};
+use std::process::Stdio;
+
+use chrono::Duration;
+use config::ConfigError;
+use tokio::process::Command;
+
+use crate::db::{PGStatActivity, Snapshot};
+use crate::format::{format_duration, format_size};
+use crate::server::ServerStatus;
+
+/// Alert rule as written in the configuration file, e.g. `rule: idle in transaction > 5m`.
+#[derive(Debug, Clone, Deserialize)]
+pub(crate) struct AlertSettings {
+    /// Defaults to the rule itself.
+    name: Option<String>,
+    rule: String,
+    /// Rings the terminal bell when the alert fires.
+    #[serde(default)]
+    bell: bool,
+    /// Shell command run when the alert fires, with the alert described in `PGTOP_*` variables.
+    command: Option<String>,
+}
+
+/// What a rule watches.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Metric {
+    /// Time spent by a backend idle in a transaction.
+    IdleInTransaction,
+    /// Time spent by an active backend running its query.
+    QueryDuration,
+    /// Time spent by a backend in its transaction.
+    XactDuration,
+    /// Number of backends waiting for a lock held by another one.
+    BlockedBackends,
+    Connections,
+    /// Replication lag in time or in bytes, depending on the unit of the threshold.
+    ReplicationLag,
+}
+
+impl Metric {
+    const ALL: [Metric; 6] = [
+        Metric::IdleInTransaction,
+        Metric::QueryDuration,
+        Metric::XactDuration,
+        Metric::BlockedBackends,
+        Metric::Connections,
+        Metric::ReplicationLag,
+    ];
+
+    fn name(self) -> &'static str {
+        match self {
+            Metric::IdleInTransaction => "idle_in_transaction",
+            Metric::QueryDuration => "query_duration",
+            Metric::XactDuration => "xact_duration",
+            Metric::BlockedBackends => "blocked_backends",
+            Metric::Connections => "connections",
+            Metric::ReplicationLag => "replication_lag",
+        }
+    }
+
+    fn accepts(self, threshold: Threshold) -> bool {
+        match self {
+            Metric::IdleInTransaction | Metric::QueryDuration | Metric::XactDuration => {
+                matches!(threshold, Threshold::Duration(_))
+            }
+            Metric::BlockedBackends | Metric::Connections => {
+                matches!(threshold, Threshold::Count(_))
+            }
+            Metric::ReplicationLag => !matches!(threshold, Threshold::Count(_)),
+        }
+    }
+
+    /// Whether the metric is measured for each backend rather than for the whole server.
+    pub fn is_per_backend(self) -> bool {
+        matches!(
+            self,
+            Metric::IdleInTransaction | Metric::QueryDuration | Metric::XactDuration
+        )
+    }
+
+    /// Time the backend has been in the state watched by a per-backend metric.
+    pub fn backend_duration(self, activity: &PGStatActivity) -> Option<Duration> {
+        let state = activity.state.as_deref().unwrap_or_default();
+        match self {
+            Metric::IdleInTransaction if state.starts_with("idle in transaction") => {
+                activity.state_duration()
+            }
+            Metric::QueryDuration if state == "active" => activity.query_duration(),
+            Metric::XactDuration => activity.xact_duration(),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Metric {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+/// Value a metric is compared to, its unit telling what is measured.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Threshold {
+    Duration(Duration),
+    Bytes(i64),
+    Count(i64),
+}
+
+impl Threshold {
+    /// Parses a number followed by an optional unit, either of time (`ms`, `s`, `m`, `h`, `d`)
+    /// or of size (`B`, `kB`, `MB`, `GB`, `TB`, multiples of 1024 as in Postgres). Values too
+    /// large to be represented are rejected.
+    fn parse(threshold: &str) -> Option<Threshold> {
+        let threshold = threshold.trim();
+        let split = threshold
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(threshold.len());
+        let (value, unit) = threshold.split_at(split);
+        let value = value.parse::<i64>().ok()?;
+        let duration = |unit: i64| value.checked_mul(unit).map(Duration::milliseconds);
+        let bytes = |unit: i64| value.checked_mul(unit).map(Threshold::Bytes);
+        let threshold = match unit.trim() {
+            "" => Threshold::Count(value),
+            "ms" => Threshold::Duration(duration(1)?),
+            "s" => Threshold::Duration(duration(1_000)?),
+            "m" | "min" => Threshold::Duration(duration(60_000)?),
+            "h" => Threshold::Duration(duration(3_600_000)?),
+            "d" => Threshold::Duration(duration(86_400_000)?),
+            "B" => bytes(1)?,
+            "kB" => bytes(1 << 10)?,
+            "MB" => bytes(1 << 20)?,
+            "GB" => bytes(1 << 30)?,
+            "TB" => bytes(1 << 40)?,
+            _ => return None,
+        };
+        Some(threshold)
+    }
+}
+
+impl fmt::Display for Threshold {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Threshold::Duration(duration) => write!(f, "{}", format_duration(duration)),
+            Threshold::Bytes(bytes) => write!(f, "{}", format_size(bytes)),
+            Threshold::Count(count) => write!(f, "{}", count),
+        }
+    }
+}
+
+/// Parses a duration written as the thresholds are, e.g. `500ms` or `15m`.
+pub(crate) fn parse_duration(duration: &str) -> Option<Duration> {
+    match Threshold::parse(duration)? {
+        Threshold::Duration(duration) => Some(duration),
+        _ => None,
+    }
+}
+
+/// Parses a condition of the form `<metric> > <threshold>`, metrics being spelled with either
+/// spaces or underscores, as in `idle in transaction > 5m`.
+pub(crate) fn parse_condition(condition: &str) -> Result<(Metric, Threshold), ConfigError> {
+    let invalid =
+        |reason: String| ConfigError::Message(format!("invalid rule '{}': {}", condition, reason));
+    let (metric, threshold) = match condition.split_once('>') {
+        Some(parts) => parts,
+        None => return Err(invalid(String::from("expected <metric> > <threshold>"))),
+    };
+    let metric_name = metric.split_whitespace().collect::<Vec<_>>().join("_");
+    let metric = match Metric::ALL
+        .iter()
+        .find(|metric| metric.name() == metric_name)
+    {
+        Some(metric) => *metric,
+        None => {
+            let names = Metric::ALL
+                .iter()
+                .map(|metric| metric.name())
+                .collect::<Vec<_>>()
+                .join(", ");
+            return Err(invalid(format!(
+                "unknown metric '{}', expected one of {}",
+                metric_name, names
+            )));
+        }
+    };
+    match Threshold::parse(threshold) {
+        Some(threshold) if metric.accepts(threshold) => Ok((metric, threshold)),
+        _ => Err(invalid(format!(
+            "invalid threshold '{}' for {}",
+            threshold.trim(),
+            metric
+        ))),
+    }
+}
+
+/// A rule checked against every snapshot, firing when the metric exceeds the threshold.
+#[derive(Debug)]
+struct Rule {
+    name: String,
+    metric: Metric,
+    threshold: Threshold,
+    bell: bool,
+    command: Option<String>,
+}
+
+impl Rule {
+    fn new(settings: &AlertSettings) -> Result<Rule, ConfigError> {
+        let (metric, threshold) = parse_condition(&settings.rule)?;
+        Ok(Rule {
+            name: settings
+                .name
+                .clone()
+                .unwrap_or_else(|| settings.rule.clone()),
+            metric,
+            threshold,
+            bell: settings.bell,
+            command: settings.command.clone(),
+        })
+    }
+
+    /// Returns the value exceeding the threshold along with the backends involved, if any.
+    fn evaluate(&self, snapshot: &Snapshot) -> Option<(String, Vec<i32>)> {
+        let activities = &snapshot.activities;
+        match (self.metric, self.threshold) {
+            (
+                Metric::IdleInTransaction | Metric::QueryDuration | Metric::XactDuration,
+                Threshold::Duration(threshold),
+            ) => {
+                let exceeding = activities
+                    .iter()
+                    .filter_map(|activity| {
+                        self.metric
+                            .backend_duration(activity)
+                            .filter(|duration| *duration > threshold)
+                            .map(|duration| (activity.pid, duration))
+                    })
+                    .collect::<Vec<_>>();
+                let longest = exceeding.iter().map(|(_, duration)| *duration).max()?;
+                let pids = exceeding.into_iter().map(|(pid, _)| pid).collect();
+                Some((format_duration(longest), pids))
+            }
+            (Metric::BlockedBackends, Threshold::Count(threshold)) => {
+                let pids = activities
+                    .iter()
+                    .filter(|activity| activity.is_blocked())
+                    .map(|activity| activity.pid)
+                    .collect::<Vec<_>>();
+                let count = pids.len() as i64;
+                if count > threshold {
+                    Some((count.to_string(), pids))
+                } else {
+                    None
+                }
+            }
+            (Metric::Connections, Threshold::Count(threshold)) => {
+                let count = snapshot.system_info.nb_of_conn;
+                if count > threshold {
+                    Some((count.to_string(), vec![]))
+                } else {
+                    None
+                }
+            }
+            (Metric::ReplicationLag, Threshold::Duration(threshold)) => snapshot
+                .system_info
+                .replication_lag()
+                .filter(|lag| *lag > threshold)
+                .map(|lag| (format_duration(lag), vec![])),
+            (Metric::ReplicationLag, Threshold::Bytes(threshold)) => snapshot
+                .system_info
+                .replication_lag_bytes
+                .filter(|lag| *lag > threshold)
+                .map(|lag| (format_size(lag), vec![])),
+            _ => None,
+        }
+    }
+
+    /// Rings the bell and starts the command of the rule, without waiting for it to complete.
+    fn trigger(&self, alert: &Alert) {
+        if self.bell {
+            // stderr is left alone by the interface and by batch mode outputs
+            let _ = io::stderr().write_all(b"\x07");
+        }
+        let command = match &self.command {
+            Some(command) => command,
+            None => return,
+        };
+        let pids = alert
+            .pids
+            .iter()
+            .map(|pid| pid.to_string())
+            .collect::<Vec<_>>()
+            .join(",");
+        let child = Command::new("sh")
+            .arg("-c")
+            .arg(command)
+            .env("PGTOP_ALERT", &alert.name)
+            .env("PGTOP_SERVER", &alert.server)
+            .env("PGTOP_METRIC", &alert.metric)
+            .env("PGTOP_VALUE", &alert.value)
+            .env("PGTOP_THRESHOLD", &alert.threshold)
+            .env("PGTOP_PIDS", pids)
+            // the output would be drawn over the interface
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn();
+        match child {
+            // reap the command once it exits
+            Ok(mut child) => {
+                tokio::spawn(async move {
+                    let _ = child.wait().await;
+                });
+            }
+            Err(err) => eprintln!("alert {}: could not run command: {}", alert.name, err),
+        }
+    }
+}
+
+/// A rule firing on a server.
+#[derive(Debug, Clone)]
+pub struct Alert {
+    pub(crate) name: String,
+    pub(crate) server: String,
+    pub(crate) metric: String,
+    pub(crate) value: String,
+    pub(crate) threshold: String,
+    /// Backends the alert is about, highlighted in the activities.
+    pub(crate) pids: Vec<i32>,
+}
+
+impl fmt::Display for Alert {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {} > {}", self.name, self.value, self.threshold)?;
+        if !self.server.is_empty() {
+            write!(f, " on {}", self.server)?;
+        }
+        Ok(())
+    }
+}
+
+/// The alert rules, along with the ones currently firing so that hooks only run when an
+/// alert starts firing rather than at every refresh.
+pub struct Alerts {
+    rules: Vec<Rule>,
+    /// Rule and server of the alerts firing at the last evaluation.
+    firing: HashSet<(usize, String)>,
+}
+
+impl Alerts {
+    /// Validates the rules of the configuration file.
+    pub fn new(settings: &[AlertSettings]) -> Result<Alerts, ConfigError> {
+        Ok(Alerts {
+            rules: settings
+                .iter()
+                .map(Rule::new)
+                .collect::<Result<Vec<_>, _>>()?,
+            firing: HashSet::new(),
+        })
+    }
+
+    /// Evaluates the rules against the snapshots, returning the alerts firing. The bell and
+    /// commands of the alerts which just started firing are triggered if `hooks` is set.
+    pub fn update(&mut self, statuses: &[ServerStatus], hooks: bool) -> Vec<Alert> {
+        let mut alerts = vec![];
+        let mut firing = HashSet::new();
+        for status in statuses {
+            let snapshot = match &status.snapshot {
+                Ok(snapshot) => snapshot,
+                Err(_) => continue,
+            };
+            for (index, rule) in self.rules.iter().enumerate() {
+                let (value, pids) = match rule.evaluate(snapshot) {
+                    Some(fired) => fired,
+                    None => continue,
+                };
+                let alert = Alert {
+                    name: rule.name.clone(),
+                    server: status.name.clone(),
+                    metric: rule.metric.to_string(),
+                    value,
+                    threshold: rule.threshold.to_string(),
+                    pids,
+                };
+                let key = (index, status.name.clone());
+                if hooks && !self.firing.contains(&key) {
+                    rule.trigger(&alert);
+                }
+                firing.insert(key);
+                alerts.push(alert);
+            }
+        }
+        self.firing = firing;
+        alerts
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Duration;
+
+    use super::{parse_condition, Metric, Threshold};
+
+    #[test]
+    fn thresholds_are_parsed_with_their_unit() {
+        let cases = [
+            ("12", Some(Threshold::Count(12))),
+            (
+                "500ms",
+                Some(Threshold::Duration(Duration::milliseconds(500))),
+            ),
+            (" 30 s ", Some(Threshold::Duration(Duration::seconds(30)))),
+            ("5m", Some(Threshold::Duration(Duration::minutes(5)))),
+            ("5min", Some(Threshold::Duration(Duration::minutes(5)))),
+            ("2h", Some(Threshold::Duration(Duration::hours(2)))),
+            ("1d", Some(Threshold::Duration(Duration::days(1)))),
+            ("10B", Some(Threshold::Bytes(10))),
+            ("64kB", Some(Threshold::Bytes(64 << 10))),
+            ("16MB", Some(Threshold::Bytes(16 << 20))),
+            ("1GB", Some(Threshold::Bytes(1 << 30))),
+            ("2TB", Some(Threshold::Bytes(2 << 40))),
+            ("", None),
+            ("ms", None),
+            ("5 minutes", None),
+            ("5kb", None),
+            ("-5s", None),
+            ("1.5h", None),
+            ("9223372036854775807d", None),
+            ("99999999999999999999", None),
+        ];
+        for (threshold, expected) in cases.iter() {
+            assert_eq!(Threshold::parse(threshold), *expected, "{}", threshold);
+        }
+    }
+
+    #[test]
+    fn conditions_are_parsed() {
+        let cases = [
+            (
+                "idle in transaction > 5m",
+                Metric::IdleInTransaction,
+                Threshold::Duration(Duration::minutes(5)),
+            ),
+            (
+                "query_duration>30s",
+                Metric::QueryDuration,
+                Threshold::Duration(Duration::seconds(30)),
+            ),
+            (
+                "  xact   duration > 1h",
+                Metric::XactDuration,
+                Threshold::Duration(Duration::hours(1)),
+            ),
+            (
+                "blocked backends > 3",
+                Metric::BlockedBackends,
+                Threshold::Count(3),
+            ),
+            (
+                "connections > 90",
+                Metric::Connections,
+                Threshold::Count(90),
+            ),
+            (
+                "replication lag > 10s",
+                Metric::ReplicationLag,
+                Threshold::Duration(Duration::seconds(10)),
+            ),
+            (
+                "replication lag > 1GB",
+                Metric::ReplicationLag,
+                Threshold::Bytes(1 << 30),
+            ),
+        ];
+        for (condition, metric, threshold) in cases.iter() {
+            assert_eq!(
+                parse_condition(condition).ok(),
+                Some((*metric, *threshold)),
+                "{}",
+                condition
+            );
+        }
+    }
+
+    #[test]
+    fn invalid_conditions_are_rejected() {
+        let cases = [
+            ("idle in transaction 5m", "expected <metric> > <threshold>"),
+            ("connections < 90", "expected <metric> > <threshold>"),
+            ("", "expected <metric> > <threshold>"),
+            ("idle > 5m", "unknown metric 'idle'"),
+            ("lock waits > 3", "unknown metric 'lock_waits'"),
+            ("> 5m", "unknown metric ''"),
+            (
+                "query duration > 5 parsecs",
+                "invalid threshold '5 parsecs'",
+            ),
+            (
+                "query duration > 5",
+                "invalid threshold '5' for query_duration",
+            ),
+            ("query duration > 5MB", "invalid threshold '5MB'"),
+            ("connections > 5s", "invalid threshold '5s' for connections"),
+            (
+                "replication lag > 5",
+                "invalid threshold '5' for replication_lag",
+            ),
+            ("blocked backends >", "invalid threshold ''"),
+            ("connections > 99999999999999999999", "invalid threshold"),
+        ];
+        for (condition, reason) in cases.iter() {
+            let err = parse_condition(condition).err().map(|err| err.to_string());
+            assert!(
+                err.as_deref().is_some_and(|err| err.contains(reason)),
+                "{}: {:?}",
+                condition,
+                err
+            );
+        }
+    }
+}
@@ -0,0 +1,169 @@
+use std::env;
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufWriter, Write};
+use std::path::PathBuf;
+
+use chrono::{DateTime, Utc};
+use tokio_postgres::Client;
+
+use crate::db::{cancel_backend, terminate_backend, PGStatActivity};
+use crate::CliResult;
+
+/// Write action taken on a backend.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Action {
+    Cancel,
+    Terminate,
+    /// Runs the query of the backend with EXPLAIN ANALYZE, see `Source::analyze`.
+    Analyze,
+}
+
+impl Action {
+    /// Sends the signal of the action to the backend, see `db::signal_backend`. Analyzing
+    /// sends none.
+    pub async fn apply(
+        self,
+        client: &Client,
+        activity: &PGStatActivity,
+    ) -> CliResult<Option<bool>> {
+        match self {
+            Action::Cancel => cancel_backend(client, activity).await,
+            Action::Terminate => terminate_backend(client, activity).await,
+            Action::Analyze => Ok(Some(false)),
+        }
+    }
+
+    /// What is said of a backend the action succeeded on.
+    pub fn done(self) -> &'static str {
+        match self {
+            Action::Cancel => "cancelled",
+            Action::Terminate => "terminated",
+            Action::Analyze => "analyzed",
+        }
+    }
+
+    /// Describes the outcome of `apply`, as written in the log.
+    pub fn describe(self, result: &CliResult<Option<bool>>) -> String {
+        match result {
+            Ok(Some(true)) => String::from(self.done()),
+            Ok(Some(false)) => String::from("failed: signal not sent"),
+            Ok(None) => String::from("skipped: backend gone"),
+            Err(err) => format!("failed: {}", err),
+        }
+    }
+}
+
+/// What came of an action, as counted once a bulk action is done.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Outcome {
+    Done,
+    Gone,
+    Failed,
+}
+
+impl Outcome {
+    /// Classifies the result of `Action::apply`.
+    pub fn of(result: &CliResult<Option<bool>>) -> Outcome {
+        match result {
+            Ok(Some(true)) => Outcome::Done,
+            Ok(None) => Outcome::Gone,
+            Ok(Some(false)) | Err(_) => Outcome::Failed,
+        }
+    }
+}
+
+impl fmt::Display for Action {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Action::Cancel => write!(f, "cancel"),
+            Action::Terminate => write!(f, "terminate"),
+            Action::Analyze => write!(f, "analyze"),
+        }
+    }
+}
+
+/// Result recorded before an action is taken, its outcome being recorded afterwards.
+pub const REQUESTED: &str = "requested";
+
+/// Record of an action, written before it is taken and once more whatever its outcome.
+#[derive(Debug, Serialize)]
+struct AuditEntry<'a> {
+    timestamp: DateTime<Utc>,
+    /// Operating system user running pgtop.
+    os_user: &'a str,
+    /// Role pgtop is connected as.
+    role: Option<&'a str>,
+    server: &'a str,
+    action: Action,
+    pid: i32,
+    /// Role of the target backend.
+    target_user: Option<&'a str>,
+    query: Option<&'a str>,
+    /// What triggered the action, e.g. the watchdog rule.
+    reason: Option<&'a str>,
+    result: &'a str,
+}
+
+/// Append-only log of the actions pgtop takes on backends, one JSON document per line.
+pub struct AuditLog {
+    writer: BufWriter<File>,
+    os_user: String,
+}
+
+impl AuditLog {
+    /// Opens the given log, or `~/.local/share/pgtop/audit.log` by default.
+    pub fn open(path: Option<&str>) -> CliResult<AuditLog> {
+        let path = match path {
+            Some(path) => PathBuf::from(path),
+            None => AuditLog::default_path(),
+        };
+        if let Some(parent) = path
+            .parent()
+            .filter(|parent| !parent.as_os_str().is_empty())
+        {
+            fs::create_dir_all(parent)?;
+        }
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(AuditLog {
+            writer: BufWriter::new(file),
+            os_user: env::var("USER").unwrap_or_default(),
+        })
+    }
+
+    fn default_path() -> PathBuf {
+        let mut path = PathBuf::from(env::var_os("HOME").unwrap_or_default());
+        path.extend(&[".local", "share", env!("CARGO_PKG_NAME"), "audit.log"]);
+        path
+    }
+
+    /// Logs an action on the backend of the activity, flushed at once so that nothing is lost
+    /// if pgtop is interrupted.
+    pub fn record(
+        &mut self,
+        server: &str,
+        role: Option<&str>,
+        action: Action,
+        activity: &PGStatActivity,
+        reason: Option<&str>,
+        result: &str,
+    ) -> CliResult<()> {
+        let entry = AuditEntry {
+            timestamp: Utc::now(),
+            os_user: &self.os_user,
+            role,
+            server,
+            action,
+            pid: activity.pid,
+            target_user: activity.usename.as_deref(),
+            query: activity.query.as_deref(),
+            reason,
+            result,
+        };
+        serde_json::to_writer(&mut self.writer, &entry)?;
+        self.writer.write_all(b"\n")?;
+        self.writer.flush()?;
+        Ok(())
+    }
+}
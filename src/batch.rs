@@ -0,0 +1,334 @@
+use std::fmt;
+use std::io::{self, Write};
+use std::str::FromStr;
+
+use chrono::{DateTime, Utc};
+
+use crate::alert::Alerts;
+use crate::db::{PGStatActivity, Snapshot};
+use crate::error::CliError;
+use crate::format::{activity_cells, system_info_cells, ACTIVITY_COLUMNS};
+use crate::recording::Recorder;
+use crate::server::take_snapshots;
+use crate::{CliResult, Context};
+
+/// Serialization of the snapshots printed in batch mode.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputFormat {
+    /// Aligned columns, meant for humans.
+    Text,
+    /// A single JSON array holding every snapshot.
+    Json,
+    /// One JSON snapshot per line.
+    Ndjson,
+    /// One record per activity, the system information being left out.
+    Csv,
+}
+
+impl OutputFormat {
+    pub const VARIANTS: [&'static str; 4] = ["text", "json", "ndjson", "csv"];
+}
+
+impl FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(OutputFormat::Text),
+            "json" => Ok(OutputFormat::Json),
+            "ndjson" => Ok(OutputFormat::Ndjson),
+            "csv" => Ok(OutputFormat::Csv),
+            _ => Err(format!("unknown output format: {}", s)),
+        }
+    }
+}
+
+impl fmt::Display for OutputFormat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            OutputFormat::Text => "text",
+            OutputFormat::Json => "json",
+            OutputFormat::Ndjson => "ndjson",
+            OutputFormat::Csv => "csv",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// Prints the header and rows with every column but the last padded to its widest cell.
+fn write_table<W: Write>(out: &mut W, header: &[&str], rows: &[Vec<String>]) -> io::Result<()> {
+    let mut widths: Vec<usize> = header.iter().map(|h| h.chars().count()).collect();
+    for row in rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+    let header = header.iter().map(|h| String::from(*h)).collect::<Vec<_>>();
+    for row in std::iter::once(&header).chain(rows) {
+        let last = row.len().saturating_sub(1);
+        let line = row
+            .iter()
+            .zip(&widths)
+            .enumerate()
+            .map(|(i, (cell, width))| {
+                if i == last {
+                    cell.clone()
+                } else {
+                    format!("{:width$}", cell, width = width)
+                }
+            })
+            .collect::<Vec<_>>()
+            .join("  ");
+        writeln!(out, "{}", line)?;
+    }
+    Ok(())
+}
+
+fn write_text<W: Write>(out: &mut W, snapshot: &Snapshot) -> io::Result<()> {
+    writeln!(
+        out,
+        "{} - {} - {}",
+        env!("CARGO_PKG_NAME"),
+        snapshot.server,
+        snapshot.timestamp.format("%F %T %Z")
+    )?;
+    writeln!(
+        out,
+        "{}",
+        system_info_cells(&snapshot.system_info).join(", ")
+    )?;
+    writeln!(out)?;
+    let rows = snapshot
+        .activities
+        .iter()
+        .map(activity_cells)
+        .collect::<Vec<_>>();
+    let header = ACTIVITY_COLUMNS
+        .iter()
+        .map(|column| column.name)
+        .collect::<Vec<_>>();
+    write_table(out, &header, &rows)?;
+    writeln!(out)
+}
+
+/// CSV record of an activity, holding the fields of its JSON serialization along with the
+/// server, the csv crate being unable to flatten nested structs.
+#[derive(Serialize)]
+struct CsvActivity<'a> {
+    server: &'a str,
+    datname: &'a Option<String>,
+    pid: i32,
+    usename: &'a Option<String>,
+    application_name: &'a Option<String>,
+    client_addr: &'a Option<String>,
+    client_port: Option<i32>,
+    backend_start: Option<DateTime<Utc>>,
+    xact_start: Option<DateTime<Utc>>,
+    query_start: Option<DateTime<Utc>>,
+    state_change: Option<DateTime<Utc>>,
+    sampled_at: DateTime<Utc>,
+    state: &'a Option<String>,
+    wait_event_type: &'a Option<String>,
+    wait_event: &'a Option<String>,
+    query: &'a Option<String>,
+    /// Space separated pids, records being flat.
+    blocked_by: String,
+}
+
+impl<'a> CsvActivity<'a> {
+    fn new(server: &'a str, activity: &'a PGStatActivity) -> CsvActivity<'a> {
+        CsvActivity {
+            server,
+            datname: &activity.datname,
+            pid: activity.pid,
+            usename: &activity.usename,
+            application_name: &activity.application_name,
+            client_addr: &activity.client_addr,
+            client_port: activity.client_port,
+            backend_start: activity.backend_start,
+            xact_start: activity.xact_start,
+            query_start: activity.query_start,
+            state_change: activity.state_change,
+            sampled_at: activity.sampled_at,
+            state: &activity.state,
+            wait_event_type: &activity.wait_event_type,
+            wait_event: &activity.wait_event,
+            query: &activity.query,
+            blocked_by: activity
+                .blocked_by
+                .iter()
+                .map(|pid| pid.to_string())
+                .collect::<Vec<_>>()
+                .join(" "),
+        }
+    }
+}
+
+/// Writes the activities as CSV records, the header is only written along the first snapshot.
+fn write_csv<W: Write>(out: &mut W, snapshot: &Snapshot, with_header: bool) -> CliResult<()> {
+    let mut writer = csv::WriterBuilder::new()
+        .has_headers(with_header)
+        .from_writer(out);
+    for activity in &snapshot.activities {
+        writer.serialize(CsvActivity::new(&snapshot.server, activity))?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+/// Writes the nth snapshot (starting at 0) in the given format.
+fn write_snapshot<W: Write>(
+    out: &mut W,
+    format: OutputFormat,
+    snapshot: &Snapshot,
+    nth: u64,
+) -> CliResult<()> {
+    match format {
+        OutputFormat::Text => write_text(out, snapshot)?,
+        OutputFormat::Json => {
+            // snapshots are streamed as the elements of an array closed when exiting
+            write!(out, "{}", if nth == 0 { "[\n" } else { ",\n" })?;
+            serde_json::to_writer_pretty(&mut *out, snapshot)?;
+        }
+        OutputFormat::Ndjson => {
+            serde_json::to_writer(&mut *out, snapshot)?;
+            writeln!(out)?;
+        }
+        OutputFormat::Csv => write_csv(out, snapshot, nth == 0)?,
+    }
+    out.flush()?;
+    Ok(())
+}
+
+/// Prints `iterations` snapshots to stdout, or until interrupted when unset, in the spirit of
+/// `top -b`. The JSON array is closed whatever stops the snapshots, Ctrl-C and errors included,
+/// snapshots being written whole between two queries, and is empty if none could be taken.
+pub async fn run(
+    mut ctx: Context,
+    mut recorder: Option<Recorder>,
+    mut alerts: Alerts,
+    iterations: Option<u64>,
+    format: OutputFormat,
+) -> CliResult<()> {
+    // snapshots written, one per server and iteration
+    let mut written = 0;
+    let result = tokio::select! {
+        result = print_snapshots(
+            &mut ctx,
+            &mut recorder,
+            &mut alerts,
+            iterations,
+            format,
+            &mut written,
+        ) => result,
+        _ = tokio::signal::ctrl_c() => Ok(()),
+    };
+    if matches!(&result, Err(err) if is_broken_pipe(err)) {
+        // the reader went away (e.g. piped into `head`), stop quietly
+        return Ok(());
+    }
+    if format == OutputFormat::Json {
+        let closed = writeln!(io::stdout(), "{}", if written > 0 { "\n]" } else { "[]" });
+        result?;
+        closed?;
+        return Ok(());
+    }
+    result
+}
+
+async fn print_snapshots(
+    ctx: &mut Context,
+    recorder: &mut Option<Recorder>,
+    alerts: &mut Alerts,
+    iterations: Option<u64>,
+    format: OutputFormat,
+    written: &mut u64,
+) -> CliResult<()> {
+    let mut printed = 0;
+    while !matches!(iterations, Some(iterations) if printed >= iterations) {
+        if printed > 0 {
+            tokio::time::sleep(ctx.refresh_rate).await;
+        }
+        let statuses = take_snapshots(&mut ctx.servers, false).await?;
+        for alert in alerts.update(&statuses, true) {
+            eprintln!("alert: {}", alert);
+        }
+        if let Some(recorder) = recorder {
+            recorder.record(
+                statuses
+                    .iter()
+                    .filter_map(|status| status.snapshot.as_ref().ok()),
+            )?;
+        }
+        for status in statuses {
+            let snapshot = match status.snapshot {
+                Ok(snapshot) => snapshot,
+                Err(err) => {
+                    eprintln!("{}: {}", status.name, err);
+                    continue;
+                }
+            };
+            write_snapshot(&mut io::stdout().lock(), format, &snapshot, *written)?;
+            *written += 1;
+        }
+        printed += 1;
+    }
+    Ok(())
+}
+
+fn is_broken_pipe(err: &CliError) -> bool {
+    match err {
+        CliError::UI { source } => source.kind() == io::ErrorKind::BrokenPipe,
+        CliError::Json { source } => source.io_error_kind() == Some(io::ErrorKind::BrokenPipe),
+        CliError::Csv { source } => match source.kind() {
+            csv::ErrorKind::Io(err) => err.kind() == io::ErrorKind::BrokenPipe,
+            _ => false,
+        },
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Utc;
+
+    use super::CsvActivity;
+    use crate::db::PGStatActivity;
+
+    #[test]
+    fn csv_records_hold_the_json_fields() {
+        let activity = PGStatActivity {
+            datname: None,
+            pid: 42,
+            usename: None,
+            application_name: None,
+            client_addr: None,
+            client_port: None,
+            backend_start: None,
+            xact_start: None,
+            query_start: None,
+            state_change: None,
+            sampled_at: Utc::now(),
+            state: None,
+            wait_event_type: None,
+            wait_event: None,
+            query: None,
+            blocked_by: vec![7, 8],
+        };
+        let mut writer = csv::Writer::from_writer(vec![]);
+        writer
+            .serialize(CsvActivity::new("primary", &activity))
+            .unwrap();
+        let csv = String::from_utf8(writer.into_inner().unwrap()).unwrap();
+        let mut lines = csv.lines();
+        let header = lines.next().unwrap().split(',').collect::<Vec<_>>();
+        let json = serde_json::to_value(&activity).unwrap();
+        let mut fields = vec!["server"];
+        fields.extend(json.as_object().unwrap().keys().map(String::as_str));
+        let mut sorted_header = header.clone();
+        sorted_header.sort_unstable();
+        fields.sort_unstable();
+        assert_eq!(sorted_header, fields);
+        assert!(lines.next().unwrap().ends_with(",7 8"));
+    }
+}
@@ -0,0 +1,81 @@
+use std::fmt;
+
+/// Server version as reported by `server_version_num`, e.g. 90624 for 9.6.24 or 150002 for 15.2.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct ServerVersion(pub(crate) i32);
+
+impl ServerVersion {
+    /// Oldest release pgtop has queries for.
+    pub const MIN_SUPPORTED: ServerVersion = ServerVersion(90600);
+}
+
+impl fmt::Display for ServerVersion {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        // major versions are made of two numbers before 10
+        if self.0 < 100000 {
+            write!(f, "{}.{}", self.0 / 10000, self.0 / 100 % 100)
+        } else {
+            write!(f, "{}", self.0 / 10000)
+        }
+    }
+}
+
+/// Server features some of the queries depend on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Feature {
+    /// `pg_stat_activity.backend_type`, before 10 the view only lists client backends.
+    BackendType,
+    /// `pg_last_wal_*` functions, named `pg_last_xlog_*_location` before 10.
+    WalFunctions,
+    /// `EXPLAIN (GENERIC_PLAN)`, planning queries with parameters without their values.
+    GenericPlan,
+}
+
+impl Feature {
+    /// Features whose absence hides information from the user, as opposed to features only
+    /// requiring another flavor of the queries.
+    pub const USER_FACING: [Feature; 1] = [Feature::BackendType];
+
+    /// First release shipping the feature.
+    pub fn min_version(self) -> ServerVersion {
+        match self {
+            Feature::BackendType | Feature::WalFunctions => ServerVersion(100000),
+            Feature::GenericPlan => ServerVersion(160000),
+        }
+    }
+}
+
+impl fmt::Display for Feature {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Feature::BackendType => write!(f, "backend type"),
+            Feature::WalFunctions => write!(f, "wal functions"),
+            Feature::GenericPlan => write!(f, "generic plans"),
+        }
+    }
+}
+
+/// Set of features available on the server pgtop is connected to.
+#[derive(Debug, Clone, Copy)]
+pub struct Capabilities {
+    pub(crate) version: ServerVersion,
+}
+
+impl Capabilities {
+    pub fn new(version: ServerVersion) -> Capabilities {
+        Capabilities { version }
+    }
+
+    pub fn supports(&self, feature: Feature) -> bool {
+        self.version >= feature.min_version()
+    }
+
+    /// Features missing from the server, surfaced to the user instead of failing queries.
+    pub fn unavailable(&self) -> Vec<Feature> {
+        Feature::USER_FACING
+            .iter()
+            .copied()
+            .filter(|feature| !self.supports(*feature))
+            .collect()
+    }
+}
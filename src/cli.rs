@@ -1,8 +1,9 @@
 use clap::{App, Arg, ArgMatches};
 
 use crate::banner::BANNER;
+use crate::batch::OutputFormat;
 
-pub fn parse_args(user: &str) -> ArgMatches {
+pub fn parse_args(user: &str) -> ArgMatches<'_> {
     let parser = App::new(env!("CARGO_PKG_NAME"))
         .version(env!("CARGO_PKG_VERSION"))
         .author(env!("CARGO_PKG_AUTHORS"))
@@ -53,6 +54,16 @@ pub fn parse_args(user: &str) -> ArgMatches {
                 .default_value(user)
                 .help(&format!(r#"Database user name (default: "{}")"#, user)),
         )
+        .arg(
+            Arg::with_name("server")
+                .short("s")
+                .long("server")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .value_name("PROFILE|CONNINFO")
+                .help("Server to monitor, either a profile of the config file or a connection string; repeat to monitor several servers"),
+        )
         .arg(
             Arg::with_name("disable_password")
                 .short("w")
@@ -65,5 +76,85 @@ pub fn parse_args(user: &str) -> ArgMatches {
                 .long("password")
                 .help("Force password prompt (should happen automatically)"),
         )
+        .arg(
+            Arg::with_name("delay")
+                .short("D")
+                .long("delay")
+                .takes_value(true)
+                .value_name("SECONDS")
+                .default_value("2.5")
+                .help(r#"Delay between refreshes (default: "2.5")"#),
+        )
+        .arg(
+            Arg::with_name("batch")
+                .short("b")
+                .long("batch")
+                .help("Print snapshots to stdout instead of starting the interactive interface"),
+        )
+        .arg(
+            Arg::with_name("iterations")
+                .short("n")
+                .long("iterations")
+                .takes_value(true)
+                .value_name("COUNT")
+                .requires("batch")
+                .help("Number of snapshots to print before exiting in batch mode (default: unlimited)"),
+        )
+        .arg(
+            Arg::with_name("output")
+                .short("o")
+                .long("output")
+                .takes_value(true)
+                .value_name("FORMAT")
+                .possible_values(&OutputFormat::VARIANTS)
+                .requires("batch")
+                .help(r#"Format of the snapshots printed in batch mode (default: "text")"#),
+        )
+        .arg(
+            Arg::with_name("exporter")
+                .long("exporter")
+                .takes_value(true)
+                .value_name("ADDRESS")
+                .conflicts_with_all(&["batch", "replay", "record"])
+                .help(r#"Serve Prometheus metrics at /metrics instead of starting the interactive interface, e.g. ":9187""#),
+        )
+        .arg(
+            Arg::with_name("watchdog")
+                .long("watchdog")
+                .conflicts_with_all(&["batch", "exporter", "replay", "record"])
+                .help("Terminate the backends matching the watchdog rules of the configuration file instead of starting the interactive interface"),
+        )
+        .arg(
+            Arg::with_name("dry_run")
+                .long("dry-run")
+                .requires("watchdog")
+                .help("Log the backends the watchdog would terminate without terminating them"),
+        )
+        .arg(
+            Arg::with_name("no_color")
+                .long("no-color")
+                .help("Draw the interactive interface without colors, as when NO_COLOR is set"),
+        )
+        .arg(
+            Arg::with_name("read_only")
+                .long("read-only")
+                .conflicts_with("watchdog")
+                .help("Disable every action changing the state of the servers, such as cancelling or terminating backends"),
+        )
+        .arg(
+            Arg::with_name("record")
+                .long("record")
+                .takes_value(true)
+                .value_name("FILE")
+                .help("Append every snapshot to a recording that can be replayed later"),
+        )
+        .arg(
+            Arg::with_name("replay")
+                .long("replay")
+                .takes_value(true)
+                .value_name("FILE")
+                .conflicts_with_all(&["batch", "record"])
+                .help("Play back a recording instead of connecting to a server"),
+        )
         .get_matches()
 }
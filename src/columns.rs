@@ -0,0 +1,298 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use config::ConfigError;
+
+use crate::format::{Column, ACTIVITY_COLUMNS, TABLE_COLUMNS};
+
+/// Key of the section of the configuration file holding the layouts.
+const SECTION: &str = "columns";
+
+/// A column as listed in the configuration file.
+#[derive(Debug, Clone, Deserialize)]
+pub(crate) struct ColumnSettings {
+    name: String,
+    width: Option<u16>,
+}
+
+/// Columns shown by each view, in display order. Views left out show every column.
+#[derive(Debug, Default, Deserialize)]
+pub(crate) struct ColumnsSettings {
+    activities: Option<Vec<ColumnSettings>>,
+    tables: Option<Vec<ColumnSettings>>,
+}
+
+/// A column of a layout.
+#[derive(Debug, Clone)]
+pub struct LayoutEntry {
+    /// Position of the column in its registry, e.g. `ACTIVITY_COLUMNS`.
+    pub(crate) index: usize,
+    pub(crate) name: &'static str,
+    pub(crate) width: u16,
+    pub(crate) visible: bool,
+}
+
+/// Order, visibility and width of the columns of a view. Every column of the registry is
+/// listed, the hidden ones included so that they can be shown again.
+#[derive(Debug, Clone)]
+pub struct ColumnLayout {
+    pub(crate) entries: Vec<LayoutEntry>,
+}
+
+impl ColumnLayout {
+    /// Lays out the columns listed in the settings first, the other ones being hidden.
+    fn new<T>(
+        registry: &[Column<T>],
+        view: &str,
+        settings: Option<&[ColumnSettings]>,
+    ) -> Result<ColumnLayout, ConfigError> {
+        let settings = match settings {
+            Some(settings) => settings,
+            None => {
+                let entries = registry
+                    .iter()
+                    .enumerate()
+                    .map(|(index, column)| LayoutEntry {
+                        index,
+                        name: column.name,
+                        width: column.width,
+                        visible: true,
+                    })
+                    .collect();
+                return Ok(ColumnLayout { entries });
+            }
+        };
+        let mut entries: Vec<LayoutEntry> = vec![];
+        for setting in settings {
+            let index = registry
+                .iter()
+                .position(|column| column.name == setting.name)
+                .ok_or_else(|| {
+                    let names = registry
+                        .iter()
+                        .map(|column| column.name)
+                        .collect::<Vec<_>>()
+                        .join(", ");
+                    ConfigError::Message(format!(
+                        "unknown column '{}' in columns.{}, expected one of {}",
+                        setting.name, view, names
+                    ))
+                })?;
+            if entries.iter().any(|entry| entry.index == index) {
+                return Err(ConfigError::Message(format!(
+                    "column '{}' is listed twice in columns.{}",
+                    setting.name, view
+                )));
+            }
+            entries.push(LayoutEntry {
+                index,
+                name: registry[index].name,
+                width: setting.width.unwrap_or(registry[index].width),
+                visible: true,
+            });
+        }
+        for (index, column) in registry.iter().enumerate() {
+            if !entries.iter().any(|entry| entry.index == index) {
+                entries.push(LayoutEntry {
+                    index,
+                    name: column.name,
+                    width: column.width,
+                    visible: false,
+                });
+            }
+        }
+        Ok(ColumnLayout { entries })
+    }
+
+    pub fn visible(&self) -> impl Iterator<Item = &LayoutEntry> {
+        self.entries.iter().filter(|entry| entry.visible)
+    }
+
+    /// Renders the visible columns of the row.
+    pub fn cells<T>(&self, registry: &[Column<T>], row: &T) -> Vec<String> {
+        self.visible()
+            .map(|entry| (registry[entry.index].cell)(row))
+            .collect()
+    }
+
+    /// Moves the nth column one position up or down, if possible.
+    pub fn shift(&mut self, position: usize, up: bool) -> usize {
+        let target = if up {
+            position.checked_sub(1)
+        } else {
+            Some(position + 1).filter(|target| *target < self.entries.len())
+        };
+        match target {
+            Some(target) => {
+                self.entries.swap(position, target);
+                target
+            }
+            None => position,
+        }
+    }
+
+    fn to_yaml(&self, view: &str) -> String {
+        let mut yaml = format!("  {}:\n", view);
+        for entry in self.visible() {
+            yaml.push_str(&format!(
+                "    - {{name: \"{}\", width: {}}}\n",
+                entry.name, entry.width
+            ));
+        }
+        yaml
+    }
+}
+
+/// Column layouts of the views.
+pub struct Layouts {
+    pub(crate) activities: ColumnLayout,
+    pub(crate) tables: ColumnLayout,
+}
+
+impl Layouts {
+    /// Validates the layouts of the configuration file.
+    pub fn new(settings: &ColumnsSettings) -> Result<Layouts, ConfigError> {
+        Ok(Layouts {
+            activities: ColumnLayout::new(
+                &ACTIVITY_COLUMNS,
+                "activities",
+                settings.activities.as_deref(),
+            )?,
+            tables: ColumnLayout::new(&TABLE_COLUMNS, "tables", settings.tables.as_deref())?,
+        })
+    }
+
+    /// Writes the layouts to the `columns` section of the configuration file, replacing the
+    /// previous one. The rest of the file, comments included, is left untouched. Files in
+    /// formats other than YAML are refused.
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        if !matches!(
+            path.extension().and_then(|extension| extension.to_str()),
+            Some("yaml") | Some("yml")
+        ) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("{} is not a YAML file", path.display()),
+            ));
+        }
+        let contents = match fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => String::new(),
+            Err(err) => return Err(err),
+        };
+        let section = format!(
+            "{}:\n{}{}",
+            SECTION,
+            self.activities.to_yaml("activities"),
+            self.tables.to_yaml("tables")
+        );
+        if let Some(parent) = path
+            .parent()
+            .filter(|parent| !parent.as_os_str().is_empty())
+        {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(path, replace_section(&contents, &section))
+    }
+}
+
+/// Replaces the top-level `columns` section of a YAML document, appending it if missing. The
+/// section spans from its key to the next line starting at the first column, other than a
+/// sequence item.
+fn replace_section(contents: &str, section: &str) -> String {
+    let mut lines = contents.lines().peekable();
+    let mut replaced = String::new();
+    let mut found = false;
+    while let Some(line) = lines.next() {
+        let is_key =
+            matches!(line.strip_prefix(SECTION), Some(rest) if rest.trim_start().starts_with(':'));
+        if !is_key || found {
+            replaced.push_str(line);
+            replaced.push('\n');
+            continue;
+        }
+        found = true;
+        replaced.push_str(section);
+        let mut trailing_blank = false;
+        while let Some(next) = lines.peek() {
+            // sequences may be written unindented under their key
+            if !next.is_empty() && !next.starts_with(char::is_whitespace) && !next.starts_with('-')
+            {
+                break;
+            }
+            trailing_blank = next.trim().is_empty();
+            lines.next();
+        }
+        // keep the section apart from the next one
+        if trailing_blank && lines.peek().is_some() {
+            replaced.push('\n');
+        }
+    }
+    if !found {
+        if !replaced.is_empty() && !replaced.ends_with("\n\n") {
+            replaced.push('\n');
+        }
+        replaced.push_str(section);
+    }
+    replaced
+}
+
+#[cfg(test)]
+mod tests {
+    use super::replace_section;
+
+    const SECTION: &str = "columns:\n  activities:\n    - {name: \"pid\", width: 7}\n";
+
+    #[test]
+    fn appends_to_empty_file() {
+        assert_eq!(replace_section("", SECTION), SECTION);
+    }
+
+    #[test]
+    fn appends_after_other_sections() {
+        assert_eq!(
+            replace_section("theme: light\n", SECTION),
+            format!("theme: light\n\n{}", SECTION)
+        );
+    }
+
+    #[test]
+    fn replaces_section_keeping_the_rest() {
+        let contents = "# servers\ntheme: light\ncolumns:\n  tables:\n    - {name: table}\n\n# keys\nkeymap:\n  quit: x\n";
+        assert_eq!(
+            replace_section(contents, SECTION),
+            format!(
+                "# servers\ntheme: light\n{}\n# keys\nkeymap:\n  quit: x\n",
+                SECTION
+            )
+        );
+    }
+
+    #[test]
+    fn replaces_unindented_sequences() {
+        let contents = "columns:\n activities:\n- {name: pid}\n- {name: user}\nread_only: true\n";
+        assert_eq!(
+            replace_section(contents, SECTION),
+            format!("{}read_only: true\n", SECTION)
+        );
+    }
+
+    #[test]
+    fn replaces_section_at_end_of_file() {
+        let contents = "theme: light\ncolumns:\n  activities:\n    - {name: user}\n\n";
+        assert_eq!(
+            replace_section(contents, SECTION),
+            format!("theme: light\n{}", SECTION)
+        );
+    }
+
+    #[test]
+    fn ignores_keys_sharing_the_prefix() {
+        let contents = "columns_width: 3\n";
+        assert_eq!(
+            replace_section(contents, SECTION),
+            format!("columns_width: 3\n\n{}", SECTION)
+        );
+    }
+}
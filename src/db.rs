@@ -1,90 +1,588 @@
+use std::convert::TryFrom;
+use std::error::Error;
+
+use bytes::BytesMut;
+use chrono::{DateTime, Duration, Utc};
+use tokio_postgres::types::{to_sql_checked, IsNull, ToSql, Type};
 use tokio_postgres::{Client, Row};
 
+use crate::capabilities::{Capabilities, Feature, ServerVersion};
+use crate::error::CliError;
+use crate::explain::{ExplainMode, ExplainSettings};
 use crate::CliResult;
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct PGSystemInfo {
     pub(crate) version: String,
-    pub(crate) uptime: String,
+    pub(crate) start_time: DateTime<Utc>,
+    pub(crate) sampled_at: DateTime<Utc>,
     pub(crate) nb_of_conn: i64,
+    /// Whether the server is a standby.
+    #[serde(default)]
+    pub(crate) in_recovery: bool,
+    /// Commit time of the last transaction replayed by a standby, or the sampling time when
+    /// it replayed everything it received.
+    #[serde(default)]
+    pub(crate) last_replay: Option<DateTime<Utc>>,
+    /// Transactions committed in every database since the statistics were reset.
+    #[serde(default)]
+    pub(crate) xact_commit: i64,
+    /// Transactions rolled back in every database since the statistics were reset.
+    #[serde(default)]
+    pub(crate) xact_rollback: i64,
+    /// WAL not replayed yet by a standby, or by the slowest standby of a primary.
+    #[serde(default)]
+    pub(crate) replication_lag_bytes: Option<i64>,
 }
 
-/// See https://www.postgresql.org/docs/9.4/monitoring-stats.html#PG-STAT-ACTIVITY-VIEW
-#[derive(Debug)]
+impl PGSystemInfo {
+    /// Time elapsed since the postmaster started.
+    pub fn uptime(&self) -> Duration {
+        self.sampled_at - self.start_time
+    }
+
+    /// How far behind its primary a standby is, None for a primary.
+    pub fn replication_lag(&self) -> Option<Duration> {
+        if !self.in_recovery {
+            return None;
+        }
+        self.last_replay
+            .map(|last_replay| self.sampled_at - last_replay)
+    }
+}
+
+/// See https://www.postgresql.org/docs/current/monitoring-stats.html#MONITORING-PG-STAT-ACTIVITY-VIEW
+/// Most columns are NULL for background processes, unix socket connections or when the role
+/// lacks the privileges to inspect another user's session.
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct PGStatActivity {
-    pub(crate) datname: String,
+    pub(crate) datname: Option<String>,
     pub(crate) pid: i32,
-    pub(crate) usename: String,
-    pub(crate) client_addr: String,
-    pub(crate) client_port: i32,
-    pub(crate) xact_start: Option<String>,
-    pub(crate) backend_duration: String,
-    pub(crate) query_duration: String,
-    pub(crate) state: String,
-    pub(crate) query: String,
-}
-
-impl From<Row> for PGStatActivity {
-    fn from(row: Row) -> PGStatActivity {
-        PGStatActivity {
-            datname: row.get("datname"),
-            pid: row.get("pid"),
-            usename: row.get("usename"),
-            client_addr: row.get("client_addr"),
-            client_port: row.get("client_port"),
-            xact_start: row.get("xact_start"),
-            backend_duration: row.get("backend_duration"),
-            query_duration: row.get("query_duration"),
-            state: row.get("state"),
-            query: row.get("query"),
+    pub(crate) usename: Option<String>,
+    #[serde(default)]
+    pub(crate) application_name: Option<String>,
+    pub(crate) client_addr: Option<String>,
+    /// -1 for unix socket connections.
+    pub(crate) client_port: Option<i32>,
+    pub(crate) backend_start: Option<DateTime<Utc>>,
+    pub(crate) xact_start: Option<DateTime<Utc>>,
+    pub(crate) query_start: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub(crate) state_change: Option<DateTime<Utc>>,
+    /// Server clock when the row was read, durations are computed relative to it.
+    pub(crate) sampled_at: DateTime<Utc>,
+    pub(crate) state: Option<String>,
+    /// Class of what the backend waits for, e.g. `Lock` or `IO`, NULL when it is not waiting.
+    #[serde(default)]
+    pub(crate) wait_event_type: Option<String>,
+    #[serde(default)]
+    pub(crate) wait_event: Option<String>,
+    pub(crate) query: Option<String>,
+    /// Backends holding the locks this one waits for.
+    #[serde(default)]
+    pub(crate) blocked_by: Vec<i32>,
+}
+
+impl PGStatActivity {
+    /// Postgres hides everything but the database, user and pid of sessions belonging to other
+    /// users unless the role is a superuser or a member of pg_read_all_stats.
+    pub fn is_visible(&self) -> bool {
+        self.backend_start.is_some()
+    }
+
+    /// Whether the backend is connected through a unix socket.
+    pub fn is_local(&self) -> bool {
+        self.client_addr.is_none() && self.client_port == Some(-1)
+    }
+
+    pub fn backend_duration(&self) -> Option<Duration> {
+        self.backend_start.map(|start| self.sampled_at - start)
+    }
+
+    pub fn xact_duration(&self) -> Option<Duration> {
+        self.xact_start.map(|start| self.sampled_at - start)
+    }
+
+    pub fn query_duration(&self) -> Option<Duration> {
+        self.query_start.map(|start| self.sampled_at - start)
+    }
+
+    /// Time spent in the current state.
+    pub fn state_duration(&self) -> Option<Duration> {
+        self.state_change.map(|change| self.sampled_at - change)
+    }
+
+    pub fn is_blocked(&self) -> bool {
+        !self.blocked_by.is_empty()
+    }
+}
+
+impl TryFrom<Row> for PGStatActivity {
+    type Error = tokio_postgres::Error;
+
+    fn try_from(row: Row) -> Result<PGStatActivity, Self::Error> {
+        Ok(PGStatActivity {
+            datname: row.try_get("datname")?,
+            pid: row.try_get("pid")?,
+            usename: row.try_get("usename")?,
+            application_name: row.try_get("application_name")?,
+            client_addr: row.try_get("client_addr")?,
+            client_port: row.try_get("client_port")?,
+            backend_start: row.try_get("backend_start")?,
+            xact_start: row.try_get("xact_start")?,
+            query_start: row.try_get("query_start")?,
+            state_change: row.try_get("state_change")?,
+            sampled_at: row.try_get("sampled_at")?,
+            state: row.try_get("state")?,
+            wait_event_type: row.try_get("wait_event_type")?,
+            wait_event: row.try_get("wait_event")?,
+            query: row.try_get("query")?,
+            blocked_by: row.try_get("blocked_by")?,
+        })
+    }
+}
+
+/// See https://www.postgresql.org/docs/current/monitoring-stats.html#MONITORING-PG-STAT-ALL-TABLES-VIEW
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PGStatUserTable {
+    pub(crate) datname: String,
+    pub(crate) schemaname: String,
+    pub(crate) relname: String,
+    pub(crate) seq_scan: i64,
+    /// NULL for tables without index.
+    pub(crate) idx_scan: Option<i64>,
+    pub(crate) n_live_tup: i64,
+    pub(crate) n_dead_tup: i64,
+    pub(crate) last_autovacuum: Option<DateTime<Utc>>,
+}
+
+impl PGStatUserTable {
+    /// Share of dead tuples, a cheap estimate of the table bloat.
+    pub fn dead_ratio(&self) -> f64 {
+        let total = self.n_live_tup + self.n_dead_tup;
+        if total == 0 {
+            0.0
+        } else {
+            self.n_dead_tup as f64 / total as f64
         }
     }
 }
 
-pub async fn get_activities(client: &Client) -> CliResult<Vec<PGStatActivity>> {
+impl TryFrom<Row> for PGStatUserTable {
+    type Error = tokio_postgres::Error;
+
+    fn try_from(row: Row) -> Result<PGStatUserTable, Self::Error> {
+        Ok(PGStatUserTable {
+            datname: row.try_get("datname")?,
+            schemaname: row.try_get("schemaname")?,
+            relname: row.try_get("relname")?,
+            seq_scan: row.try_get("seq_scan")?,
+            idx_scan: row.try_get("idx_scan")?,
+            n_live_tup: row.try_get("n_live_tup")?,
+            n_dead_tup: row.try_get("n_dead_tup")?,
+            last_autovacuum: row.try_get("last_autovacuum")?,
+        })
+    }
+}
+
+/// Server statistics collected at a single point in time.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Snapshot {
+    /// Name of the server the snapshot was taken from.
+    #[serde(default)]
+    pub(crate) server: String,
+    pub(crate) timestamp: DateTime<Utc>,
+    pub(crate) system_info: PGSystemInfo,
+    pub(crate) activities: Vec<PGStatActivity>,
+    /// Tables of every database, only collected while they are displayed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) tables: Option<Vec<PGStatUserTable>>,
+    /// Why the tables of some databases are missing, if any.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub(crate) table_errors: Vec<String>,
+}
+
+/// Reads the server version and infers the features available to the queries.
+pub async fn get_capabilities(client: &Client) -> CliResult<Capabilities> {
+    let row = client
+        .query_one("SELECT current_setting('server_version_num')::integer", &[])
+        .await?;
+    let version = ServerVersion(row.try_get(0)?);
+    if version < ServerVersion::MIN_SUPPORTED {
+        return Err(CliError::UnsupportedServer { version });
+    }
+    Ok(Capabilities::new(version))
+}
+
+pub async fn get_activities(
+    client: &Client,
+    capabilities: &Capabilities,
+) -> CliResult<Vec<PGStatActivity>> {
+    query_activities(client, capabilities, &[]).await
+}
+
+/// Active backends other than pgtop's own, as sampled for the session history.
+pub async fn get_active_sessions(
+    client: &Client,
+    capabilities: &Capabilities,
+) -> CliResult<Vec<PGStatActivity>> {
+    query_activities(
+        client,
+        capabilities,
+        &["state = 'active'", "pid <> pg_backend_pid()"],
+    )
+    .await
+}
+
+async fn query_activities(
+    client: &Client,
+    capabilities: &Capabilities,
+    conditions: &[&str],
+) -> CliResult<Vec<PGStatActivity>> {
     // todo: decide on using diesel instead of raw (untyped) query
-    let activities_query = r"
+    // clock_timestamp() is used rather than current_timestamp since the latter is frozen at the
+    // start of the transaction, which may predate the start of the backends being listed.
+    // only client backends are listed before 10
+    // pg_blocking_pids() is costly when called often, it is only called for the backends
+    // waiting for a lock, the only ones it finds blockers for
+    let mut conditions = conditions.to_vec();
+    if capabilities.supports(Feature::BackendType) {
+        conditions.insert(0, "backend_type = 'client backend'");
+    }
+    let backend_filter = if conditions.is_empty() {
+        String::new()
+    } else {
+        format!("WHERE {}", conditions.join(" AND "))
+    };
+    let activities_query = format!(
+        r"
         SELECT datname,
         pid,
         usename,
+        application_name,
         client_addr::text,
         client_port,
-        xact_start::text,
-        to_char(current_timestamp - backend_start, 'HH24:MI:SS:MS') AS backend_duration,
-        to_char(current_timestamp - query_start, 'HH24:MI:SS:MS')::text AS query_duration,
+        backend_start,
+        xact_start,
+        query_start,
+        state_change,
+        clock_timestamp() AS sampled_at,
         state,
-        query
+        wait_event_type,
+        wait_event,
+        query,
+        CASE WHEN wait_event_type = 'Lock' THEN pg_blocking_pids(pid)
+             ELSE '{{}}'::integer[]
+        END AS blocked_by
  FROM pg_stat_activity
- WHERE backend_type = 'client backend'
-        ";
+ {}
+        ",
+        backend_filter
+    );
     let stats = client
-        .query(activities_query, &[])
+        .query(activities_query.as_str(), &[])
         .await?
         .into_iter()
-        .map(PGStatActivity::from)
-        .collect::<Vec<_>>();
+        .map(PGStatActivity::try_from)
+        .collect::<Result<Vec<_>, _>>()?;
     Ok(stats)
 }
 
-pub async fn get_system_info(client: &Client) -> CliResult<PGSystemInfo> {
-    // https://www.postgresql.org/docs/9.2/monitoring-stats.html#PG-STAT-DATABASE-VIEW
-    let system_info_query = r"
+pub async fn get_system_info(
+    client: &Client,
+    capabilities: &Capabilities,
+) -> CliResult<PGSystemInfo> {
+    // https://www.postgresql.org/docs/current/monitoring-stats.html#MONITORING-PG-STAT-DATABASE-VIEW
+    // A standby which replayed all the WAL it received is up to date, however long ago the last
+    // transaction replayed was committed.
+    // WAL functions were named after xlog before 10
+    let (receive_lsn, replay_lsn, current_lsn, lsn_diff, replay_lsn_column) =
+        if capabilities.supports(Feature::WalFunctions) {
+            (
+                "pg_last_wal_receive_lsn()",
+                "pg_last_wal_replay_lsn()",
+                "pg_current_wal_lsn()",
+                "pg_wal_lsn_diff",
+                "replay_lsn",
+            )
+        } else {
+            (
+                "pg_last_xlog_receive_location()",
+                "pg_last_xlog_replay_location()",
+                "pg_current_xlog_location()",
+                "pg_xlog_location_diff",
+                "replay_location",
+            )
+        };
+    // The byte lag of a standby is the WAL it received but did not replay yet, the one of a
+    // primary is the WAL its slowest standby did not replay yet.
+    let system_info_query = format!(
+        r"
   SELECT version(),
-         justify_interval(current_timestamp - pg_postmaster_start_time())::text,
-         sum(numbackends)
-    FROM pg_stat_database";
+         pg_postmaster_start_time(),
+         clock_timestamp(),
+         sum(numbackends),
+         pg_is_in_recovery(),
+         CASE WHEN {receive} = {replay} THEN clock_timestamp()
+              ELSE pg_last_xact_replay_timestamp()
+         END,
+         sum(xact_commit)::bigint,
+         sum(xact_rollback)::bigint,
+         CASE WHEN pg_is_in_recovery() THEN {diff}({receive}, {replay})
+              ELSE (SELECT max({diff}({current}, {replay_column})) FROM pg_stat_replication)
+         END::bigint
+    FROM pg_stat_database",
+        receive = receive_lsn,
+        replay = replay_lsn,
+        current = current_lsn,
+        diff = lsn_diff,
+        replay_column = replay_lsn_column,
+    );
     // retrieve version
-    let row = client.query_one(system_info_query, &[]).await?;
+    let row = client.query_one(system_info_query.as_str(), &[]).await?;
 
     // And then check that we got back the same string we sent over.
-    let version: String = row.get(0);
-    // notes: https://github.com/sfackler/rust-postgres/issues/60
-    let uptime: String = row.get(1);
-    let nb_of_conn: i64 = row.get(2);
+    let version: String = row.try_get(0)?;
+    let start_time: DateTime<Utc> = row.try_get(1)?;
+    let sampled_at: DateTime<Utc> = row.try_get(2)?;
+    // sum() yields NULL when pg_stat_database is empty
+    let nb_of_conn: Option<i64> = row.try_get(3)?;
+    let in_recovery: bool = row.try_get(4)?;
+    let last_replay: Option<DateTime<Utc>> = row.try_get(5)?;
+    let xact_commit: Option<i64> = row.try_get(6)?;
+    let xact_rollback: Option<i64> = row.try_get(7)?;
+    let replication_lag_bytes: Option<i64> = row.try_get(8)?;
 
     Ok(PGSystemInfo {
         version,
-        uptime,
-        nb_of_conn,
+        start_time,
+        sampled_at,
+        nb_of_conn: nb_of_conn.unwrap_or(0),
+        in_recovery,
+        last_replay,
+        xact_commit: xact_commit.unwrap_or(0),
+        xact_rollback: xact_rollback.unwrap_or(0),
+        replication_lag_bytes,
+    })
+}
+
+pub async fn get_snapshot(client: &Client, capabilities: &Capabilities) -> CliResult<Snapshot> {
+    let system_info = get_system_info(client, capabilities).await?;
+    let activities = get_activities(client, capabilities).await?;
+    Ok(Snapshot {
+        server: String::new(),
+        timestamp: system_info.sampled_at,
+        system_info,
+        activities,
+        tables: None,
+        table_errors: vec![],
+    })
+}
+
+/// Counts the backends waiting to acquire a heavyweight lock.
+pub async fn get_lock_waits(client: &Client) -> CliResult<i64> {
+    let lock_waits_query = "SELECT count(DISTINCT pid) FROM pg_locks WHERE NOT granted";
+    let row = client.query_one(lock_waits_query, &[]).await?;
+    Ok(row.try_get(0)?)
+}
+
+/// Roles whose backends are never terminated automatically: superusers and replication roles.
+pub async fn get_protected_roles(client: &Client) -> CliResult<Vec<String>> {
+    let roles_query = "SELECT rolname::text FROM pg_roles WHERE rolsuper OR rolreplication";
+    let roles = client
+        .query(roles_query, &[])
+        .await?
+        .into_iter()
+        .map(|row| row.try_get(0))
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(roles)
+}
+
+/// Pid of the backend serving the client.
+pub async fn get_backend_pid(client: &Client) -> CliResult<i32> {
+    let row = client.query_one("SELECT pg_backend_pid()", &[]).await?;
+    Ok(row.try_get(0)?)
+}
+
+/// Sends a signal to the backend through `function`, provided it is still the one of the
+/// snapshot rather than a new backend reusing its pid. With `unchanged`, the backend must also
+/// still be in the state, query and transaction of the snapshot. Returns `None` when the backend
+/// is gone (or changed), else whether the signal could be sent.
+async fn signal_backend(
+    client: &Client,
+    function: &str,
+    activity: &PGStatActivity,
+    unchanged: bool,
+) -> CliResult<Option<bool>> {
+    let state_filter = if unchanged {
+        r"
+     AND state IS NOT DISTINCT FROM $3
+     AND state_change IS NOT DISTINCT FROM $4
+     AND query_start IS NOT DISTINCT FROM $5
+     AND xact_start IS NOT DISTINCT FROM $6"
+    } else {
+        ""
+    };
+    let signal_query = format!(
+        r"
+  SELECT {}(pid)
+    FROM pg_stat_activity
+   WHERE pid = $1
+     AND backend_start = $2{}",
+        function, state_filter
+    );
+    let row = if unchanged {
+        client
+            .query_opt(
+                signal_query.as_str(),
+                &[
+                    &activity.pid,
+                    &activity.backend_start,
+                    &activity.state,
+                    &activity.state_change,
+                    &activity.query_start,
+                    &activity.xact_start,
+                ],
+            )
+            .await?
+    } else {
+        client
+            .query_opt(
+                signal_query.as_str(),
+                &[&activity.pid, &activity.backend_start],
+            )
+            .await?
+    };
+    Ok(row.map(|row| row.try_get(0)).transpose()?)
+}
+
+/// Cancels the query of the backend, see `signal_backend`.
+pub async fn cancel_backend(client: &Client, activity: &PGStatActivity) -> CliResult<Option<bool>> {
+    signal_backend(client, "pg_cancel_backend", activity, false).await
+}
+
+/// Terminates the backend, see `signal_backend`.
+pub async fn terminate_backend(
+    client: &Client,
+    activity: &PGStatActivity,
+) -> CliResult<Option<bool>> {
+    signal_backend(client, "pg_terminate_backend", activity, false).await
+}
+
+/// Terminates the backend unless it left the state, query or transaction of the snapshot, so
+/// that a rule on their durations still holds when the signal is sent, see `signal_backend`.
+pub async fn terminate_unchanged_backend(
+    client: &Client,
+    activity: &PGStatActivity,
+) -> CliResult<Option<bool>> {
+    signal_backend(client, "pg_terminate_backend", activity, true).await
+}
+
+/// Bounds the time a query explained with ANALYZE can run for.
+const ANALYZE_TIMEOUT: &str = "30s";
+/// Bounds the time planning a query can take, and waiting for the locks of its relations, so
+/// that explaining a query stuck behind a lock does not get stuck as well.
+const PLAN_TIMEOUT: &str = "5s";
+
+/// Binds NULL whatever the type, for the parameters of generic plans which are never run.
+#[derive(Debug)]
+struct Null;
+
+impl ToSql for Null {
+    fn to_sql(&self, _: &Type, _: &mut BytesMut) -> Result<IsNull, Box<dyn Error + Sync + Send>> {
+        Ok(IsNull::Yes)
+    }
+
+    fn accepts(_: &Type) -> bool {
+        true
+    }
+
+    to_sql_checked!();
+}
+
+pub async fn get_explain_settings(client: &Client) -> CliResult<ExplainSettings> {
+    // pg_settings hides the superuser-only settings rather than failing
+    let settings_query = r"
+  SELECT (SELECT setting::int FROM pg_settings WHERE name = 'track_activity_query_size'),
+         EXISTS (SELECT 1
+                   FROM pg_settings
+                  WHERE name IN ('shared_preload_libraries', 'session_preload_libraries')
+                    AND setting LIKE '%auto_explain%')";
+    let row = client.query_one(settings_query, &[]).await?;
+    let track_activity_query_size: i32 = row.try_get(0)?;
+    Ok(ExplainSettings {
+        track_activity_query_size: usize::try_from(track_activity_query_size).unwrap_or(0),
+        auto_explain: row.try_get(1)?,
     })
 }
+
+/// Explains the query, one line of the plan per row. The extended protocol refuses query texts
+/// made of several statements. The query is explained in a read-only transaction rolled back
+/// afterwards, the server refusing any write a query run with ANALYZE would attempt, and waits
+/// for locks for `PLAN_TIMEOUT` at most. It runs for `ANALYZE_TIMEOUT` at most when analyzed
+/// and is planned within `PLAN_TIMEOUT` otherwise.
+pub async fn explain(client: &Client, query: &str, mode: ExplainMode) -> CliResult<Vec<String>> {
+    let explain_query = format!("EXPLAIN {}{}", mode.options(), query);
+    let statement_timeout = match mode {
+        ExplainMode::Analyze => ANALYZE_TIMEOUT,
+        ExplainMode::Plan | ExplainMode::GenericPlan => PLAN_TIMEOUT,
+    };
+    client
+        .batch_execute(&format!(
+            "BEGIN READ ONLY; SET LOCAL statement_timeout = '{}'; SET LOCAL lock_timeout = '{}'",
+            statement_timeout, PLAN_TIMEOUT
+        ))
+        .await?;
+    let rows = async {
+        if mode == ExplainMode::Analyze {
+            return client.query(explain_query.as_str(), &[]).await;
+        }
+        let statement = client.prepare(&explain_query).await?;
+        let params = vec![&Null as &(dyn ToSql + Sync); statement.params().len()];
+        client.query(&statement, &params).await
+    }
+    .await;
+    client.batch_execute("ROLLBACK").await?;
+    Ok(rows?
+        .into_iter()
+        .map(|row| row.try_get(0))
+        .collect::<Result<Vec<_>, _>>()?)
+}
+
+/// Lists the databases pgtop can connect to.
+pub async fn get_databases(client: &Client) -> CliResult<Vec<String>> {
+    let databases_query = r"
+  SELECT datname
+    FROM pg_database
+   WHERE datallowconn
+     AND NOT datistemplate
+ORDER BY datname";
+    let databases = client
+        .query(databases_query, &[])
+        .await?
+        .into_iter()
+        .map(|row| row.try_get(0))
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(databases)
+}
+
+/// Statistics of the tables of the database the client is connected to.
+pub async fn get_tables(client: &Client) -> CliResult<Vec<PGStatUserTable>> {
+    let tables_query = r"
+  SELECT current_database()::text AS datname,
+         schemaname::text,
+         relname::text,
+         seq_scan,
+         idx_scan,
+         n_live_tup,
+         n_dead_tup,
+         last_autovacuum
+    FROM pg_stat_user_tables";
+    let tables = client
+        .query(tables_query, &[])
+        .await?
+        .into_iter()
+        .map(PGStatUserTable::try_from)
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(tables)
+}
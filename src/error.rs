@@ -1,11 +1,21 @@
 use std::io;
 use std::sync::mpsc;
+use std::time::Duration;
+
+use crate::capabilities::ServerVersion;
 
 #[derive(Debug)]
 pub enum CliError {
     DB { source: tokio_postgres::Error },
     UI { source: io::Error },
     Pipe { source: mpsc::RecvError },
+    UnsupportedServer { version: ServerVersion },
+    Json { source: serde_json::Error },
+    Csv { source: csv::Error },
+    EmptyRecording,
+    ReadOnly,
+    Explain { reason: String },
+    Timeout { after: Duration },
 }
 
 impl std::fmt::Display for CliError {
@@ -14,6 +24,20 @@ impl std::fmt::Display for CliError {
             CliError::DB { ref source } => write!(f, "{}", source),
             CliError::UI { ref source } => write!(f, "{}", source),
             CliError::Pipe { ref source } => write!(f, "{}", source),
+            CliError::UnsupportedServer { version } => write!(
+                f,
+                "unsupported server version {}, at least {} is required",
+                version,
+                ServerVersion::MIN_SUPPORTED
+            ),
+            CliError::Json { ref source } => write!(f, "{}", source),
+            CliError::Csv { ref source } => write!(f, "{}", source),
+            CliError::EmptyRecording => write!(f, "the recording does not hold any snapshot"),
+            CliError::ReadOnly => write!(f, "disabled in read-only mode"),
+            CliError::Explain { ref reason } => write!(f, "cannot explain the query: {}", reason),
+            CliError::Timeout { after } => {
+                write!(f, "no answer from the server within {}s", after.as_secs())
+            }
         }
     }
 }
@@ -24,6 +48,13 @@ impl std::error::Error for CliError {
             CliError::DB { ref source } => Some(source),
             CliError::UI { ref source } => Some(source),
             CliError::Pipe { ref source } => Some(source),
+            CliError::UnsupportedServer { .. } => None,
+            CliError::Json { ref source } => Some(source),
+            CliError::Csv { ref source } => Some(source),
+            CliError::EmptyRecording => None,
+            CliError::ReadOnly => None,
+            CliError::Explain { .. } => None,
+            CliError::Timeout { .. } => None,
         }
     }
 }
@@ -45,3 +76,15 @@ impl From<mpsc::RecvError> for CliError {
         CliError::Pipe { source: err }
     }
 }
+
+impl From<serde_json::Error> for CliError {
+    fn from(err: serde_json::Error) -> CliError {
+        CliError::Json { source: err }
+    }
+}
+
+impl From<csv::Error> for CliError {
+    fn from(err: csv::Error) -> CliError {
+        CliError::Csv { source: err }
+    }
+}
@@ -1,9 +1,6 @@
 use std::io;
-use std::sync::mpsc;
-use std::sync::{
-    atomic::{AtomicBool, Ordering},
-    Arc,
-};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc};
 use std::thread;
 use std::time::Duration;
 
@@ -13,43 +10,34 @@ use termion::input::TermRead;
 pub enum Event<I> {
     Input(I),
     Tick,
+    /// Time to sample the active backends, more often than the refreshes.
+    Sample,
 }
 
 /// A small event handler that wrap termion input and tick events. Each event
-/// type is handled in its own thread and returned to a common `Receiver`
+/// type is handled in its own thread and returned to a common `Receiver`, the threads
+/// ending along with the program.
 pub struct Events {
     rx: mpsc::Receiver<Event<Key>>,
-    input_handle: thread::JoinHandle<()>,
-    ignore_exit_key: Arc<AtomicBool>,
-    tick_handle: thread::JoinHandle<()>,
+    /// Whether a sample is queued or being taken, the sampling thread skipping its turns until
+    /// `sampled` is called so that slow servers do not pile samples up.
+    sampling: Arc<AtomicBool>,
 }
 
 #[derive(Debug, Clone, Copy)]
 pub struct Config {
-    pub exit_key: Key,
     pub tick_rate: Duration,
-}
-
-impl Default for Config {
-    fn default() -> Config {
-        Config {
-            exit_key: Key::Char('q'),
-            tick_rate: Duration::from_millis(2500),
-        }
-    }
+    /// No samples are asked for when unset.
+    pub sample_rate: Option<Duration>,
 }
 
 impl Events {
-    pub fn new() -> Events {
-        Events::with_config(Config::default())
-    }
-
     pub fn with_config(config: Config) -> Events {
         let (tx, rx) = mpsc::channel();
-        let ignore_exit_key = Arc::new(AtomicBool::new(false));
-        let input_handle = {
+        let sampling = Arc::new(AtomicBool::new(false));
+        {
             let tx = tx.clone();
-            let ignore_exit_key = ignore_exit_key.clone();
+            // quitting is up to the key bindings
             thread::spawn(move || {
                 let stdin = io::stdin();
                 for key in stdin.keys().flatten() {
@@ -57,37 +45,37 @@ impl Events {
                         eprintln!("{}", err);
                         return;
                     }
-                    if !ignore_exit_key.load(Ordering::Relaxed) && key == config.exit_key {
-                        return;
-                    }
                 }
-            })
-        };
-        let tick_handle = {
+            });
+        }
+        if let Some(sample_rate) = config.sample_rate {
+            let tx = tx.clone();
+            let sampling = Arc::clone(&sampling);
             thread::spawn(move || loop {
-                if tx.send(Event::Tick).is_err() {
+                thread::sleep(sample_rate);
+                if sampling.swap(true, Ordering::AcqRel) {
+                    continue;
+                }
+                if tx.send(Event::Sample).is_err() {
                     break;
                 }
-                thread::sleep(config.tick_rate);
-            })
-        };
-        Events {
-            rx,
-            input_handle,
-            ignore_exit_key,
-            tick_handle,
+            });
         }
+        thread::spawn(move || loop {
+            if tx.send(Event::Tick).is_err() {
+                break;
+            }
+            thread::sleep(config.tick_rate);
+        });
+        Events { rx, sampling }
     }
 
     pub fn next(&self) -> Result<Event<Key>, mpsc::RecvError> {
         self.rx.recv()
     }
 
-    pub fn disable_exit_key(&mut self) {
-        self.ignore_exit_key.store(true, Ordering::Relaxed);
-    }
-
-    pub fn enable_exit_key(&mut self) {
-        self.ignore_exit_key.store(false, Ordering::Relaxed);
+    /// Lets the next sample be asked for, once the last one is taken.
+    pub fn sampled(&self) {
+        self.sampling.store(false, Ordering::Release);
     }
 }
@@ -0,0 +1,279 @@
+use crate::capabilities::{Capabilities, Feature};
+use crate::error::CliError;
+use crate::CliResult;
+
+/// Statements EXPLAIN accepts, by first keyword.
+const PLANNABLE: [&str; 8] = [
+    "select", "with", "values", "table", "insert", "update", "delete", "merge",
+];
+
+/// Words of the statements changing data, or locking rows, which are never run with
+/// EXPLAIN ANALYZE, e.g. within a data-modifying WITH or as `SELECT ... FOR UPDATE` and
+/// `SELECT ... INTO`.
+const MODIFYING: [&str; 6] = ["insert", "update", "delete", "merge", "into", "share"];
+
+/// How a query is explained.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExplainMode {
+    /// The plan the query would get, without running it.
+    Plan,
+    /// A plan valid for any value of the parameters, without running the query.
+    GenericPlan,
+    /// The plan along with the actual figures of running the query.
+    Analyze,
+}
+
+impl ExplainMode {
+    /// Options of the EXPLAIN statement.
+    pub fn options(self) -> &'static str {
+        match self {
+            ExplainMode::Plan => "",
+            ExplainMode::GenericPlan => "(GENERIC_PLAN) ",
+            ExplainMode::Analyze => "(ANALYZE) ",
+        }
+    }
+}
+
+/// Settings of the server bearing on explaining the queries of its backends.
+#[derive(Debug, Clone, Copy)]
+pub struct ExplainSettings {
+    /// Bytes of the queries kept by `pg_stat_activity`, longer ones being truncated.
+    pub(crate) track_activity_query_size: usize,
+    /// Whether `auto_explain` is preloaded, logging the plans of the slow queries.
+    pub(crate) auto_explain: bool,
+}
+
+/// Default of `track_activity_query_size`, assumed when the setting of the server is unknown.
+pub const DEFAULT_QUERY_SIZE: usize = 1024;
+
+/// Whether `pg_stat_activity` hides the query from pgtop, showing it only to superusers, to
+/// members of `pg_read_all_stats` and to the role running it.
+pub fn is_hidden(query: &str) -> bool {
+    query.is_empty() || query == "<insufficient privilege>"
+}
+
+/// Whether the query fills the `track_activity_query_size` bytes kept by `pg_stat_activity`,
+/// terminator included, and was most likely cut there. Postgres cuts at a character boundary,
+/// so a query cut before a character of up to 4 bytes may stop 3 bytes short of the limit.
+pub fn is_truncated(query: &str, track_activity_query_size: usize) -> bool {
+    query.len() + 4 >= track_activity_query_size
+}
+
+fn refuse(reason: &str) -> CliError {
+    CliError::Explain {
+        reason: String::from(reason),
+    }
+}
+
+/// Picks how to explain the query as shown by `pg_stat_activity`, refusing the queries which
+/// cannot be planned as they are. Queries with parameters get a generic plan when the server
+/// supports them, and are never run.
+pub fn explain_mode(
+    query: &str,
+    analyze: bool,
+    capabilities: &Capabilities,
+    settings: &ExplainSettings,
+) -> CliResult<ExplainMode> {
+    if is_hidden(query) {
+        return Err(refuse("the query is hidden from pgtop"));
+    }
+    if is_truncated(query, settings.track_activity_query_size) {
+        return Err(refuse(
+            "the query is truncated, see track_activity_query_size",
+        ));
+    }
+    let keyword = first_keyword(query);
+    if keyword.is_empty() {
+        return Err(refuse("the query holds no statement"));
+    }
+    if !PLANNABLE.contains(&keyword.as_str()) {
+        return Err(CliError::Explain {
+            reason: format!("{} statements have no plan", keyword.to_uppercase()),
+        });
+    }
+    if analyze && !is_read_only(query) {
+        return Err(refuse(
+            "only queries reading data are run, this one may change or lock rows",
+        ));
+    }
+    if !has_parameters(query) {
+        return Ok(if analyze {
+            ExplainMode::Analyze
+        } else {
+            ExplainMode::Plan
+        });
+    }
+    if analyze {
+        return Err(refuse(
+            "the query has parameters, it cannot be run without their values",
+        ));
+    }
+    if capabilities.supports(Feature::GenericPlan) {
+        return Ok(ExplainMode::GenericPlan);
+    }
+    Err(refuse(if settings.auto_explain {
+        "the query has parameters, see the plans logged by auto_explain"
+    } else {
+        "the query has parameters, generic plans require PostgreSQL 16"
+    }))
+}
+
+/// The query with its comments, literals, quoted names and dollar-quoted strings blanked out,
+/// leaving the keywords, names, operators and placeholders.
+fn code(query: &str) -> String {
+    let mut code = String::with_capacity(query.len());
+    let mut rest = query;
+    let mut previous = ' ';
+    while let Some(c) = rest.chars().next() {
+        let skipped = if rest.starts_with("--") {
+            rest.find('\n').unwrap_or(rest.len())
+        } else if let Some(comment) = rest.strip_prefix("/*") {
+            comment.find("*/").map_or(rest.len(), |end| end + 4)
+        } else if c == '\'' || c == '"' {
+            rest[1..].find(c).map_or(rest.len(), |end| end + 2)
+        } else if let Some(tag) = dollar_tag(rest).filter(|_| !is_word_char(previous)) {
+            rest[tag.len()..]
+                .find(tag)
+                .map_or(rest.len(), |end| end + 2 * tag.len())
+        } else {
+            code.push(c);
+            previous = c;
+            rest = &rest[c.len_utf8()..];
+            continue;
+        };
+        code.push(' ');
+        previous = ' ';
+        rest = &rest[skipped..];
+    }
+    code
+}
+
+/// Opening tag of the dollar-quoted string the text starts with, e.g. `$$` or `$body$`.
+fn dollar_tag(text: &str) -> Option<&str> {
+    let tag = text.strip_prefix('$')?;
+    let end = tag.find(|c: char| !is_word_char(c))?;
+    if tag.starts_with(|c: char| c.is_ascii_digit()) || !tag[end..].starts_with('$') {
+        return None;
+    }
+    Some(&text[..end + 2])
+}
+
+fn is_word_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+/// Words of the query outside of comments, literals and quoted names, in lowercase.
+fn words(query: &str) -> Vec<String> {
+    code(query)
+        .to_lowercase()
+        .split(|c: char| !(is_word_char(c) || c == '$'))
+        .filter(|word| !word.is_empty())
+        .map(String::from)
+        .collect()
+}
+
+/// First keyword of the query, past comments and opening parentheses, in lowercase.
+fn first_keyword(query: &str) -> String {
+    code(query)
+        .trim_start_matches(|c: char| c.is_whitespace() || c == '(')
+        .chars()
+        .take_while(|c| c.is_ascii_alphabetic())
+        .collect::<String>()
+        .to_lowercase()
+}
+
+/// Whether running the query only reads data: a SELECT, VALUES or TABLE statement, or a WITH
+/// query none of whose parts changes data. An early refusal only, functions writing data being
+/// left to the read-only transaction the query runs in.
+fn is_read_only(query: &str) -> bool {
+    let words = words(query);
+    matches!(
+        words.first().map(String::as_str),
+        Some("select") | Some("values") | Some("table") | Some("with")
+    ) && !words.iter().any(|word| MODIFYING.contains(&word.as_str()))
+}
+
+/// Whether the query holds placeholders such as `$1`, outside of comments, literals and quoted
+/// names.
+fn has_parameters(query: &str) -> bool {
+    let code = code(query);
+    let mut previous = ' ';
+    let mut chars = code.chars().peekable();
+    while let Some(c) = chars.next() {
+        // `$` may also belong to an identifier, e.g. `a$1`
+        if c == '$'
+            && !is_word_char(previous)
+            && matches!(chars.peek(), Some(d) if d.is_ascii_digit())
+        {
+            return true;
+        }
+        previous = c;
+    }
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{first_keyword, has_parameters, is_read_only, is_truncated};
+
+    #[test]
+    fn first_keyword_skips_comments_and_parentheses() {
+        assert_eq!(first_keyword("SELECT 1"), "select");
+        assert_eq!(
+            first_keyword("  -- report\n/* v2 */ ((select 1))"),
+            "select"
+        );
+        assert_eq!(first_keyword("/* unterminated"), "");
+        assert_eq!(first_keyword("-- only a comment"), "");
+        assert_eq!(first_keyword("Vacuum t"), "vacuum");
+    }
+
+    #[test]
+    fn has_parameters_finds_placeholders() {
+        assert!(has_parameters("select * from t where id = $1"));
+        assert!(has_parameters("select * from t where id=$12"));
+        assert!(has_parameters("select ($1)"));
+    }
+
+    #[test]
+    fn has_parameters_ignores_quotes_comments_and_identifiers() {
+        assert!(!has_parameters("select '$1'"));
+        assert!(!has_parameters("select \"$1\" from t"));
+        assert!(!has_parameters("select a$1 from t"));
+        assert!(!has_parameters("select 1 -- $1"));
+        assert!(!has_parameters("select /* $1 */ 1"));
+        assert!(!has_parameters("select $$ $1 $$, $body$ $2 $body$"));
+        assert!(!has_parameters("select 'it''s $1'"));
+    }
+
+    #[test]
+    fn is_read_only_refuses_data_changes() {
+        assert!(is_read_only("select * from t"));
+        assert!(is_read_only("(values (1))"));
+        assert!(is_read_only("table t"));
+        assert!(is_read_only("with c as (select 1) select * from c"));
+        assert!(is_read_only("select 'delete' from t -- update"));
+        assert!(!is_read_only("insert into t values (1)"));
+        assert!(!is_read_only(
+            "with d as (delete from t returning *) select * from d"
+        ));
+        assert!(!is_read_only("select * into copy from t"));
+        assert!(!is_read_only("select * from t for update"));
+        assert!(!is_read_only("select * from t for key share"));
+    }
+
+    #[test]
+    fn is_truncated_allows_for_multibyte_characters() {
+        assert!(!is_truncated("select 1", 1024));
+        assert!(!is_truncated("abcde", 10));
+        assert!(is_truncated("abcdef", 10));
+        assert!(is_truncated("abcdefghi", 10));
+        // a character which did not fit in the 1023 bytes kept leaves up to 3 of them unused
+        let query = format!("select '{}", "é".repeat(507));
+        assert_eq!(query.len(), 1022);
+        assert!(is_truncated(&query, 1024));
+        let query = format!("select '{}", "🐘".repeat(253));
+        assert_eq!(query.len(), 1020);
+        assert!(is_truncated(&query, 1024));
+    }
+}
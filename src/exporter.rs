@@ -0,0 +1,264 @@
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::time::Duration as StdDuration;
+
+use chrono::Duration;
+use futures_util::future::join_all;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+use crate::db::Snapshot;
+use crate::server::Server;
+use crate::{CliResult, Context};
+
+/// Queries running for longer are counted as long-running.
+const LONG_QUERY_THRESHOLD_SECONDS: i64 = 60;
+/// Upper bound of the request head, scrapers send a handful of headers.
+const MAX_REQUEST_SIZE: usize = 8192;
+/// Scrapes being answered in turn, a stalled client must not hold the others.
+const REQUEST_TIMEOUT: StdDuration = StdDuration::from_secs(30);
+/// Bounds the time the snapshot of a server takes, and then its lock waits, both together
+/// staying within `REQUEST_TIMEOUT`.
+const SERVER_TIMEOUT: StdDuration = StdDuration::from_secs(10);
+
+/// Samples of a metric, rendered in the Prometheus text exposition format.
+struct MetricFamily {
+    name: &'static str,
+    help: &'static str,
+    kind: &'static str,
+    samples: Vec<(String, f64)>,
+}
+
+impl MetricFamily {
+    fn new(name: &'static str, kind: &'static str, help: &'static str) -> MetricFamily {
+        MetricFamily {
+            name,
+            help,
+            kind,
+            samples: vec![],
+        }
+    }
+
+    fn add(&mut self, labels: &[(&str, &str)], value: f64) {
+        let labels = labels
+            .iter()
+            .map(|(name, value)| format!("{}=\"{}\"", name, escape_label(value)))
+            .collect::<Vec<_>>()
+            .join(",");
+        self.samples.push((labels, value));
+    }
+
+    fn render(&self, out: &mut String) {
+        let _ = writeln!(out, "# HELP {} {}", self.name, self.help);
+        let _ = writeln!(out, "# TYPE {} {}", self.name, self.kind);
+        for (labels, value) in &self.samples {
+            let _ = writeln!(out, "{}{{{}}} {}", self.name, labels, value);
+        }
+    }
+}
+
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+fn seconds(duration: Duration) -> f64 {
+    duration.num_milliseconds().max(0) as f64 / 1000.0
+}
+
+/// Takes a snapshot of every server at once, along with its number of lock waits, so that a
+/// server which stopped answering is reported down rather than failing the scrape.
+async fn collect(servers: &mut [Server]) -> Vec<(String, CliResult<(Snapshot, i64)>)> {
+    join_all(servers.iter_mut().map(|server| async move {
+        let result = match server.snapshot_within(SERVER_TIMEOUT, false).await {
+            Ok(snapshot) => server
+                .lock_waits_within(SERVER_TIMEOUT)
+                .await
+                .map(|lock_waits| (snapshot, lock_waits)),
+            Err(err) => Err(err),
+        };
+        (server.name.clone(), result)
+    }))
+    .await
+}
+
+fn render(collected: &[(String, CliResult<(Snapshot, i64)>)]) -> String {
+    let mut up = MetricFamily::new("pgtop_up", "gauge", "Whether the server could be queried.");
+    let mut uptime = MetricFamily::new(
+        "pgtop_uptime_seconds",
+        "gauge",
+        "Time elapsed since the server started.",
+    );
+    let mut connections =
+        MetricFamily::new("pgtop_connections", "gauge", "Client backends by state.");
+    let mut long_running = MetricFamily::new(
+        "pgtop_long_running_queries",
+        "gauge",
+        "Active queries running for more than a minute.",
+    );
+    let mut oldest_query = MetricFamily::new(
+        "pgtop_oldest_query_seconds",
+        "gauge",
+        "Duration of the longest running active query.",
+    );
+    let mut oldest_xact = MetricFamily::new(
+        "pgtop_oldest_transaction_seconds",
+        "gauge",
+        "Duration of the longest running transaction.",
+    );
+    let mut lock_waits = MetricFamily::new(
+        "pgtop_lock_waits",
+        "gauge",
+        "Backends waiting to acquire a lock.",
+    );
+    let mut replication_lag = MetricFamily::new(
+        "pgtop_replication_lag_seconds",
+        "gauge",
+        "Time since the last transaction replayed by a standby, 0 when caught up.",
+    );
+    let mut commits = MetricFamily::new(
+        "pgtop_xact_commit_total",
+        "counter",
+        "Transactions committed across all databases.",
+    );
+    let mut rollbacks = MetricFamily::new(
+        "pgtop_xact_rollback_total",
+        "counter",
+        "Transactions rolled back across all databases.",
+    );
+
+    for (server, result) in collected {
+        let labels = [("server", server.as_str())];
+        let (snapshot, waits) = match result {
+            Ok(collected) => collected,
+            Err(_) => {
+                up.add(&labels, 0.0);
+                continue;
+            }
+        };
+        up.add(&labels, 1.0);
+        let system_info = &snapshot.system_info;
+        uptime.add(&labels, seconds(system_info.uptime()));
+
+        let mut by_state = BTreeMap::new();
+        for activity in &snapshot.activities {
+            let state = activity.state.as_deref().unwrap_or("unknown");
+            *by_state.entry(state).or_insert(0) += 1;
+        }
+        for (state, count) in by_state {
+            connections.add(&[("server", server), ("state", state)], f64::from(count));
+        }
+
+        let active_query_durations = snapshot
+            .activities
+            .iter()
+            .filter(|activity| activity.state.as_deref() == Some("active"))
+            .filter_map(|activity| activity.query_duration())
+            .collect::<Vec<_>>();
+        let long_running_count = active_query_durations
+            .iter()
+            .filter(|duration| duration.num_seconds() >= LONG_QUERY_THRESHOLD_SECONDS)
+            .count();
+        long_running.add(&labels, long_running_count as f64);
+        let longest_query = active_query_durations.into_iter().max();
+        oldest_query.add(&labels, longest_query.map_or(0.0, seconds));
+        let longest_xact = snapshot
+            .activities
+            .iter()
+            .filter_map(|activity| activity.xact_duration())
+            .max();
+        oldest_xact.add(&labels, longest_xact.map_or(0.0, seconds));
+
+        lock_waits.add(&labels, *waits as f64);
+        if let Some(lag) = system_info.replication_lag() {
+            replication_lag.add(&labels, seconds(lag));
+        }
+        commits.add(&labels, system_info.xact_commit as f64);
+        rollbacks.add(&labels, system_info.xact_rollback as f64);
+    }
+
+    let mut out = String::new();
+    for family in &[
+        up,
+        uptime,
+        connections,
+        long_running,
+        oldest_query,
+        oldest_xact,
+        lock_waits,
+        replication_lag,
+        commits,
+        rollbacks,
+    ] {
+        family.render(&mut out);
+    }
+    out
+}
+
+/// Answers a single HTTP request, the metrics being collected anew for every scrape.
+async fn serve(servers: &mut [Server], mut stream: TcpStream) -> CliResult<()> {
+    let mut request = Vec::new();
+    let mut buffer = [0u8; 1024];
+    while !request.windows(4).any(|window| window == b"\r\n\r\n") {
+        let read = stream.read(&mut buffer).await?;
+        if read == 0 || request.len() + read > MAX_REQUEST_SIZE {
+            return Ok(());
+        }
+        request.extend_from_slice(&buffer[..read]);
+    }
+    let request = String::from_utf8_lossy(&request);
+    let mut request_line = request
+        .lines()
+        .next()
+        .unwrap_or_default()
+        .split_whitespace();
+    let (status, content_type, body) = match (request_line.next(), request_line.next()) {
+        (Some("GET"), Some("/metrics")) => (
+            "200 OK",
+            "text/plain; version=0.0.4",
+            render(&collect(servers).await),
+        ),
+        (Some("GET"), Some("/")) => (
+            "200 OK",
+            "text/html",
+            String::from(r#"<html><body><a href="/metrics">Metrics</a></body></html>"#),
+        ),
+        (Some("GET"), _) => ("404 Not Found", "text/plain", String::from("not found\n")),
+        _ => (
+            "405 Method Not Allowed",
+            "text/plain",
+            String::from("method not allowed\n"),
+        ),
+    };
+    let response = format!(
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        content_type,
+        body.len(),
+        body
+    );
+    stream.write_all(response.as_bytes()).await?;
+    stream.shutdown().await?;
+    Ok(())
+}
+
+/// Serves the metrics of the servers at `/metrics` until interrupted. The address is either
+/// `host:port` or `:port` to listen on every interface.
+pub async fn run(mut ctx: Context, address: &str) -> CliResult<()> {
+    let address = match address.strip_prefix(':') {
+        Some(port) => format!("0.0.0.0:{}", port),
+        None => String::from(address),
+    };
+    let listener = TcpListener::bind(address).await?;
+    loop {
+        let (stream, _) = listener.accept().await?;
+        // scrapes are answered in turn since they share the connections to the servers
+        match tokio::time::timeout(REQUEST_TIMEOUT, serve(&mut ctx.servers, stream)).await {
+            Ok(Err(err)) => eprintln!("exporter: {}", err),
+            Err(_) => eprintln!("exporter: request timed out"),
+            Ok(Ok(())) => {}
+        }
+    }
+}
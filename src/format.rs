@@ -0,0 +1,226 @@
+use chrono::Duration;
+
+use crate::db::{PGStatActivity, PGStatUserTable, PGSystemInfo};
+
+/// Shown in place of the columns Postgres hides from roles lacking the privileges.
+const INSUFFICIENT_PRIVILEGE: &str = "<insufficient privilege>";
+/// Shown in place of the client address of unix socket connections.
+const LOCAL_CLIENT: &str = "local";
+
+/// A column of the rows of type `T`, as listed in the tables of the interface and of batch mode.
+pub struct Column<T> {
+    pub name: &'static str,
+    /// Default width in the interactive interface, in characters.
+    pub width: u16,
+    pub cell: fn(&T) -> String,
+}
+
+/// Columns of the activities table, in default display order.
+pub const ACTIVITY_COLUMNS: [Column<PGStatActivity>; 12] = [
+    Column {
+        name: "database",
+        width: 10,
+        cell: |activity| activity.datname.clone().unwrap_or_default(),
+    },
+    Column {
+        name: "pid",
+        width: 7,
+        cell: |activity| activity.pid.to_string(),
+    },
+    Column {
+        name: "user",
+        width: 10,
+        cell: |activity| activity.usename.clone().unwrap_or_default(),
+    },
+    Column {
+        name: "client_addr",
+        width: 15,
+        cell: format_client_addr,
+    },
+    Column {
+        name: "client_port",
+        width: 11,
+        cell: |activity| match activity.client_port {
+            Some(port) if port >= 0 => port.to_string(),
+            _ => String::new(),
+        },
+    },
+    Column {
+        name: "backend duration",
+        width: 16,
+        cell: |activity| format_optional_duration(activity.backend_duration()),
+    },
+    Column {
+        name: "xact duration",
+        width: 16,
+        cell: |activity| format_optional_duration(activity.xact_duration()),
+    },
+    Column {
+        name: "query duration",
+        width: 16,
+        cell: |activity| format_optional_duration(activity.query_duration()),
+    },
+    Column {
+        name: "state",
+        width: 10,
+        cell: |activity| format_nullable(activity.state.as_deref(), activity.is_visible()),
+    },
+    Column {
+        name: "wait_event_type",
+        width: 15,
+        cell: |activity| activity.wait_event_type.clone().unwrap_or_default(),
+    },
+    Column {
+        name: "wait_event",
+        width: 20,
+        cell: |activity| activity.wait_event.clone().unwrap_or_default(),
+    },
+    Column {
+        name: "query",
+        width: 50,
+        cell: |activity| {
+            format_nullable(
+                activity.query.as_deref().map(format_query).as_deref(),
+                activity.is_visible(),
+            )
+        },
+    },
+];
+
+/// Columns of the tables table, in default display order.
+pub const TABLE_COLUMNS: [Column<PGStatUserTable>; 9] = [
+    Column {
+        name: "database",
+        width: 10,
+        cell: |table| table.datname.clone(),
+    },
+    Column {
+        name: "schema",
+        width: 10,
+        cell: |table| table.schemaname.clone(),
+    },
+    Column {
+        name: "table",
+        width: 20,
+        cell: |table| table.relname.clone(),
+    },
+    Column {
+        name: "seq scans",
+        width: 11,
+        cell: |table| table.seq_scan.to_string(),
+    },
+    Column {
+        name: "index scans",
+        width: 11,
+        cell: |table| {
+            table
+                .idx_scan
+                .map(|idx_scan| idx_scan.to_string())
+                .unwrap_or_default()
+        },
+    },
+    Column {
+        name: "live tuples",
+        width: 11,
+        cell: |table| table.n_live_tup.to_string(),
+    },
+    Column {
+        name: "dead tuples",
+        width: 11,
+        cell: |table| table.n_dead_tup.to_string(),
+    },
+    Column {
+        name: "dead %",
+        width: 6,
+        cell: |table| format!("{:.1}", table.dead_ratio() * 100.0),
+    },
+    Column {
+        name: "last autovacuum",
+        width: 23,
+        cell: |table| {
+            table
+                .last_autovacuum
+                .map(|last_autovacuum| last_autovacuum.format("%F %T %Z").to_string())
+                .unwrap_or_default()
+        },
+    },
+];
+
+/// Renders a duration as `HH:MM:SS.mmm`, or as `Nd HH:MM:SS` once it exceeds a day.
+pub fn format_duration(duration: Duration) -> String {
+    // durations are computed from server timestamps, clamp any jitter below zero
+    let duration = duration.max(Duration::zero());
+    let days = duration.num_days();
+    let hours = duration.num_hours() % 24;
+    let minutes = duration.num_minutes() % 60;
+    let seconds = duration.num_seconds() % 60;
+    if days > 0 {
+        format!("{}d {:02}:{:02}:{:02}", days, hours, minutes, seconds)
+    } else {
+        let millis = duration.num_milliseconds() % 1000;
+        format!("{:02}:{:02}:{:02}.{:03}", hours, minutes, seconds, millis)
+    }
+}
+
+/// Renders a size in bytes with the largest unit keeping it above 1, as `pg_size_pretty` does.
+pub fn format_size(bytes: i64) -> String {
+    const UNITS: [&str; 4] = ["kB", "MB", "GB", "TB"];
+    let mut size = bytes as f64;
+    let mut unit = "B";
+    for next_unit in UNITS.iter() {
+        if size.abs() < 1024.0 {
+            break;
+        }
+        size /= 1024.0;
+        unit = next_unit;
+    }
+    if unit == "B" {
+        format!("{} {}", bytes, unit)
+    } else {
+        format!("{:.1} {}", size, unit)
+    }
+}
+
+pub fn format_optional_duration(duration: Option<Duration>) -> String {
+    duration.map(format_duration).unwrap_or_default()
+}
+
+/// Renders a nullable column, NULL values of hidden sessions are replaced by a placeholder.
+pub fn format_nullable(value: Option<&str>, visible: bool) -> String {
+    match value {
+        Some(value) => String::from(value),
+        None if !visible => String::from(INSUFFICIENT_PRIVILEGE),
+        None => String::new(),
+    }
+}
+
+/// Renders the client address, unix socket connections being shown as local.
+pub fn format_client_addr(activity: &PGStatActivity) -> String {
+    if activity.is_local() {
+        String::from(LOCAL_CLIENT)
+    } else {
+        format_nullable(activity.client_addr.as_deref(), activity.is_visible())
+    }
+}
+
+/// Collapses the query on a single line so it fits in a table row.
+fn format_query(query: &str) -> String {
+    query.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// Renders every column of an activity, in the order of `ACTIVITY_COLUMNS`.
+pub fn activity_cells(activity: &PGStatActivity) -> Vec<String> {
+    ACTIVITY_COLUMNS
+        .iter()
+        .map(|column| (column.cell)(activity))
+        .collect()
+}
+
+/// Renders the server wide statistics as `label: value` pairs.
+pub fn system_info_cells(system_info: &PGSystemInfo) -> Vec<String> {
+    vec![
+        format!("version: {}", system_info.version),
+        format!("uptime: {}", format_duration(system_info.uptime())),
+        format!("active connections: {}", system_info.nb_of_conn),
+    ]
+}
@@ -0,0 +1,105 @@
+use chrono::Duration;
+
+use crate::db::PGStatActivity;
+use crate::format::{format_client_addr, format_nullable};
+use crate::wait::format_wait_event;
+
+/// Attribute the activities can be collapsed by.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GroupKey {
+    Database,
+    User,
+    ClientAddr,
+    Application,
+    State,
+    WaitEvent,
+}
+
+impl GroupKey {
+    /// Every key, in the order they are cycled through.
+    pub const ALL: [GroupKey; 6] = [
+        GroupKey::Database,
+        GroupKey::User,
+        GroupKey::ClientAddr,
+        GroupKey::Application,
+        GroupKey::State,
+        GroupKey::WaitEvent,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            GroupKey::Database => "database",
+            GroupKey::User => "user",
+            GroupKey::ClientAddr => "client_addr",
+            GroupKey::Application => "application_name",
+            GroupKey::State => "state",
+            GroupKey::WaitEvent => "wait_event",
+        }
+    }
+
+    /// Value of the attribute for the activity, as shown in the activities table.
+    pub fn value(self, activity: &PGStatActivity) -> String {
+        let visible = activity.is_visible();
+        match self {
+            GroupKey::Database => activity.datname.clone().unwrap_or_default(),
+            GroupKey::User => activity.usename.clone().unwrap_or_default(),
+            GroupKey::ClientAddr => format_client_addr(activity),
+            GroupKey::Application => format_nullable(activity.application_name.as_deref(), visible),
+            GroupKey::State => format_nullable(activity.state.as_deref(), visible),
+            GroupKey::WaitEvent => format_wait_event(activity).unwrap_or_default(),
+        }
+    }
+
+    /// Key following `key` in `ALL`, no grouping coming after the last one and before the first.
+    pub fn cycle(key: Option<GroupKey>) -> Option<GroupKey> {
+        match key {
+            None => Some(GroupKey::ALL[0]),
+            Some(key) => {
+                let i = GroupKey::ALL.iter().position(|other| *other == key)?;
+                GroupKey::ALL.get(i + 1).copied()
+            }
+        }
+    }
+}
+
+/// Activities sharing the value of a `GroupKey`.
+#[derive(Debug, Clone)]
+pub struct Group {
+    pub(crate) value: String,
+    pub(crate) count: usize,
+    /// Backends running a query.
+    pub(crate) active: usize,
+    /// Longest running query, idle backends being left out.
+    pub(crate) oldest_query: Option<Duration>,
+    pub(crate) oldest_xact: Option<Duration>,
+}
+
+/// Collapses the activities by the key, the largest groups first.
+pub fn group_activities(activities: &[PGStatActivity], key: GroupKey) -> Vec<Group> {
+    let mut groups: Vec<Group> = vec![];
+    for activity in activities {
+        let value = key.value(activity);
+        let i = match groups.iter().position(|group| group.value == value) {
+            Some(i) => i,
+            None => {
+                groups.push(Group {
+                    value,
+                    count: 0,
+                    active: 0,
+                    oldest_query: None,
+                    oldest_xact: None,
+                });
+                groups.len() - 1
+            }
+        };
+        let group = &mut groups[i];
+        group.count += 1;
+        if activity.state.as_deref() == Some("active") {
+            group.active += 1;
+            group.oldest_query = group.oldest_query.max(activity.query_duration());
+        }
+        group.oldest_xact = group.oldest_xact.max(activity.xact_duration());
+    }
+    groups.sort_by(|a, b| b.count.cmp(&a.count).then_with(|| a.value.cmp(&b.value)));
+    groups
+}
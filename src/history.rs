@@ -0,0 +1,286 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::Arc;
+use std::time::Duration as StdDuration;
+
+use chrono::{DateTime, Duration, Utc};
+use config::ConfigError;
+
+use crate::alert::parse_duration;
+use crate::db::PGStatActivity;
+use crate::server::ServerStatus;
+use crate::wait::{count_by, is_active, wait_class, wait_label};
+
+const DEFAULT_SAMPLE_INTERVAL: &str = "1s";
+const DEFAULT_RETENTION: &str = "1h";
+/// Shortest delay between two samples, each of which queries every server.
+const MIN_SAMPLE_INTERVAL_MS: i64 = 100;
+/// Longest retention, which also keeps the oldest sample within the dates chrono represents.
+const MAX_RETENTION_DAYS: i64 = 7;
+/// Lengths of the time windows the samples can be summed up over, the longest ones being left
+/// out past the retention.
+const WINDOWS: [(&str, i64); 6] = [
+    ("1m", 60),
+    ("5m", 5 * 60),
+    ("15m", 15 * 60),
+    ("1h", 60 * 60),
+    ("6h", 6 * 60 * 60),
+    ("24h", 24 * 60 * 60),
+];
+/// Window shown first, if kept long enough.
+const DEFAULT_WINDOW: usize = 1;
+
+/// History section of the configuration file.
+#[derive(Debug, Default, Deserialize)]
+pub(crate) struct HistorySettings {
+    /// Delay between two samples of the active backends, `1s` by default.
+    sample_interval: Option<String>,
+    /// How long the samples are kept, `1h` by default.
+    retention: Option<String>,
+}
+
+fn parse_setting(name: &str, value: Option<&str>, default: &str) -> Result<Duration, ConfigError> {
+    let value = value.unwrap_or(default);
+    match parse_duration(value) {
+        Some(duration) if duration > Duration::zero() => Ok(duration),
+        _ => Err(ConfigError::Message(format!(
+            "invalid history {} '{}', expected a positive duration such as {}",
+            name, value, default
+        ))),
+    }
+}
+
+/// An active backend as sampled.
+struct Session {
+    pid: i32,
+    /// Interned, as are the wait events and their classes.
+    user: Arc<str>,
+    wait_class: Arc<str>,
+    wait_event: Arc<str>,
+    /// Shared with the samples before as long as the backend runs the same query.
+    query: Arc<str>,
+}
+
+struct Sample {
+    sampled_at: DateTime<Utc>,
+    sessions: Vec<Session>,
+}
+
+/// Figures of the samples taken within a time window.
+pub struct Summary {
+    pub from: DateTime<Utc>,
+    pub to: DateTime<Utc>,
+    /// Active backends of each sample, oldest first.
+    pub active: Vec<usize>,
+    /// Samples of active backends by query, user and wait event, the most frequent first.
+    pub queries: Vec<(Arc<str>, usize)>,
+    pub users: Vec<(Arc<str>, usize)>,
+    pub wait_events: Vec<(Arc<str>, usize)>,
+}
+
+impl Summary {
+    /// Average number of active backends over the window, a figure known as average active
+    /// sessions.
+    pub fn average(&self, count: usize) -> f64 {
+        count as f64 / self.active.len().max(1) as f64
+    }
+}
+
+/// Shared copy of the name, which is added to the names seen so far if new.
+fn intern(names: &mut HashSet<Arc<str>>, name: &str) -> Arc<str> {
+    if let Some(name) = names.get(name) {
+        return Arc::clone(name);
+    }
+    let name: Arc<str> = Arc::from(name);
+    names.insert(Arc::clone(&name));
+    name
+}
+
+/// Active session history: samples of the active backends of every server, taken more often
+/// than the refreshes and kept for the retention so that a past spike can still be looked into.
+pub struct History {
+    /// Delay between two samples, the sampling being left to the interactive interface.
+    pub(crate) sample_interval: StdDuration,
+    retention: Duration,
+    samples: HashMap<String, VecDeque<Sample>>,
+    /// Users, wait events and wait classes seen in the samples, few as they are.
+    names: HashSet<Arc<str>>,
+}
+
+impl History {
+    pub fn new(settings: &HistorySettings) -> Result<History, ConfigError> {
+        let sample_interval = parse_setting(
+            "sample_interval",
+            settings.sample_interval.as_deref(),
+            DEFAULT_SAMPLE_INTERVAL,
+        )?;
+        let retention = parse_setting(
+            "retention",
+            settings.retention.as_deref(),
+            DEFAULT_RETENTION,
+        )?;
+        if sample_interval < Duration::milliseconds(MIN_SAMPLE_INTERVAL_MS) {
+            return Err(ConfigError::Message(format!(
+                "invalid history sample_interval, shorter than {}ms",
+                MIN_SAMPLE_INTERVAL_MS
+            )));
+        }
+        if retention > Duration::days(MAX_RETENTION_DAYS) {
+            return Err(ConfigError::Message(format!(
+                "invalid history retention, longer than {}d",
+                MAX_RETENTION_DAYS
+            )));
+        }
+        if retention < sample_interval {
+            return Err(ConfigError::Message(String::from(
+                "invalid history retention, shorter than the sample interval",
+            )));
+        }
+        Ok(History {
+            sample_interval: sample_interval.to_std().expect("checked positive"),
+            retention,
+            samples: HashMap::new(),
+            names: HashSet::new(),
+        })
+    }
+
+    /// Records the active backends of a server. A sample taken at the same time as the last
+    /// one is ignored, and an older one, as happens when seeking backward in a recording,
+    /// starts the history over.
+    pub fn record(
+        &mut self,
+        server: &str,
+        sampled_at: DateTime<Utc>,
+        activities: &[PGStatActivity],
+    ) {
+        let names = &mut self.names;
+        let samples = self.samples.entry(String::from(server)).or_default();
+        match samples.back() {
+            Some(last) if last.sampled_at == sampled_at => return,
+            Some(last) if last.sampled_at > sampled_at => samples.clear(),
+            _ => {}
+        }
+        let oldest = sampled_at - self.retention;
+        while matches!(samples.front(), Some(first) if first.sampled_at < oldest) {
+            samples.pop_front();
+        }
+        let previous = samples.back();
+        let sessions = activities
+            .iter()
+            .filter(|activity| is_active(activity))
+            .map(|activity| {
+                let text = activity.query.as_deref().unwrap_or_default();
+                let query = previous
+                    .and_then(|previous| {
+                        previous
+                            .sessions
+                            .iter()
+                            .find(|session| session.pid == activity.pid && &*session.query == text)
+                    })
+                    .map_or_else(|| Arc::from(text), |session| Arc::clone(&session.query));
+                Session {
+                    pid: activity.pid,
+                    user: intern(names, activity.usename.as_deref().unwrap_or_default()),
+                    wait_class: intern(names, wait_class(activity)),
+                    wait_event: intern(names, &wait_label(activity)),
+                    query,
+                }
+            })
+            .collect();
+        samples.push_back(Sample {
+            sampled_at,
+            sessions,
+        });
+    }
+
+    /// Records the snapshots of the servers which could be queried, the only samples there are
+    /// when replaying a recording.
+    pub fn record_statuses(&mut self, statuses: &[ServerStatus]) {
+        for status in statuses {
+            if let Ok(snapshot) = &status.snapshot {
+                self.record(&status.name, snapshot.timestamp, &snapshot.activities);
+            }
+        }
+    }
+
+    /// Windows available, by name, those longer than the retention but the first one left out.
+    pub fn windows(&self) -> Vec<&'static str> {
+        let mut windows = vec![];
+        for (name, seconds) in WINDOWS.iter() {
+            windows.push(*name);
+            if Duration::seconds(*seconds) >= self.retention {
+                break;
+            }
+        }
+        windows
+    }
+
+    pub fn default_window(&self) -> usize {
+        DEFAULT_WINDOW.min(self.windows().len() - 1)
+    }
+
+    pub fn window_length(window: usize) -> Duration {
+        Duration::seconds(WINDOWS[window].1)
+    }
+
+    /// How far back the window can be moved, its end staying within the samples of the server.
+    pub fn span(&self, server: &str) -> Duration {
+        match self
+            .samples
+            .get(server)
+            .map(|samples| (samples.front(), samples.back()))
+        {
+            Some((Some(first), Some(last))) => last.sampled_at - first.sampled_at,
+            _ => Duration::zero(),
+        }
+    }
+
+    /// Sums up the samples of the server within the window ending `before` the last sample.
+    pub fn summary(&self, server: &str, window: Duration, before: Duration) -> Option<Summary> {
+        let samples = self.samples.get(server)?;
+        let to = samples.back()?.sampled_at - before;
+        let from = to - window;
+        let samples = samples
+            .iter()
+            .filter(|sample| sample.sampled_at > from && sample.sampled_at <= to)
+            .collect::<Vec<_>>();
+        let sessions = || samples.iter().flat_map(|sample| sample.sessions.iter());
+        Some(Summary {
+            from,
+            to,
+            active: samples.iter().map(|sample| sample.sessions.len()).collect(),
+            queries: count_by(sessions().map(|session| Arc::clone(&session.query))),
+            users: count_by(sessions().map(|session| Arc::clone(&session.user))),
+            wait_events: count_by(sessions().map(|session| Arc::clone(&session.wait_event))),
+        })
+    }
+
+    /// Active backends of each wait class seen in the last samples of the server, oldest sample
+    /// first, the classes with the most backends overall first.
+    pub fn classes(&self, server: &str, count: usize) -> Vec<(Arc<str>, Vec<usize>)> {
+        let samples = match self.samples.get(server) {
+            Some(samples) => samples,
+            None => return vec![],
+        };
+        let samples = samples
+            .iter()
+            .skip(samples.len().saturating_sub(count))
+            .collect::<Vec<_>>();
+        let sessions = samples.iter().flat_map(|sample| sample.sessions.iter());
+        count_by(sessions.map(|session| Arc::clone(&session.wait_class)))
+            .into_iter()
+            .map(|(class, _)| {
+                let counts = samples
+                    .iter()
+                    .map(|sample| {
+                        sample
+                            .sessions
+                            .iter()
+                            .filter(|session| session.wait_class == class)
+                            .count()
+                    })
+                    .collect();
+                (class, counts)
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,640 @@
+use std::collections::HashMap;
+
+use config::ConfigError;
+use termion::event::Key;
+
+/// Keys bound to a command in the configuration file, either a single key or a list.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+pub(crate) enum KeyBindingSettings {
+    One(String),
+    Many(Vec<String>),
+}
+
+impl KeyBindingSettings {
+    fn keys(&self) -> &[String] {
+        match self {
+            KeyBindingSettings::One(key) => std::slice::from_ref(key),
+            KeyBindingSettings::Many(keys) => keys,
+        }
+    }
+}
+
+/// Everything the interactive interface can be asked to do from the keyboard.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Command {
+    Help,
+    Quit,
+    Down,
+    Up,
+    PageDown,
+    PageUp,
+    First,
+    Last,
+    ScrollLeft,
+    ScrollRight,
+    JumpToPid,
+    Filter,
+    Tag,
+    TagAll,
+    UntagAll,
+    Explain,
+    Analyze,
+    Copy,
+    GroupBy,
+    Expand,
+    WaitEvents,
+    History,
+    NarrowWindow,
+    WidenWindow,
+    Close,
+    NextServer,
+    PreviousServer,
+    ToggleTables,
+    Setup,
+    MoveColumnLeft,
+    MoveColumnRight,
+    Refresh,
+    Cancel,
+    Terminate,
+    PlayPause,
+    StepForward,
+    StepBackward,
+    SeekForward,
+    SeekBackward,
+    SeekStart,
+    SeekEnd,
+}
+
+impl Command {
+    /// Every command, in the order of the help screen.
+    pub const ALL: [Command; 41] = [
+        Command::Help,
+        Command::Quit,
+        Command::Down,
+        Command::Up,
+        Command::PageDown,
+        Command::PageUp,
+        Command::First,
+        Command::Last,
+        Command::ScrollLeft,
+        Command::ScrollRight,
+        Command::JumpToPid,
+        Command::Filter,
+        Command::Tag,
+        Command::TagAll,
+        Command::UntagAll,
+        Command::Explain,
+        Command::Analyze,
+        Command::Copy,
+        Command::GroupBy,
+        Command::Expand,
+        Command::WaitEvents,
+        Command::History,
+        Command::NarrowWindow,
+        Command::WidenWindow,
+        Command::Close,
+        Command::NextServer,
+        Command::PreviousServer,
+        Command::ToggleTables,
+        Command::Setup,
+        Command::MoveColumnLeft,
+        Command::MoveColumnRight,
+        Command::Refresh,
+        Command::Cancel,
+        Command::Terminate,
+        Command::PlayPause,
+        Command::StepForward,
+        Command::StepBackward,
+        Command::SeekForward,
+        Command::SeekBackward,
+        Command::SeekStart,
+        Command::SeekEnd,
+    ];
+
+    /// Name of the command in the keymap section of the configuration file.
+    pub fn name(self) -> &'static str {
+        match self {
+            Command::Help => "help",
+            Command::Quit => "quit",
+            Command::Down => "down",
+            Command::Up => "up",
+            Command::PageDown => "page_down",
+            Command::PageUp => "page_up",
+            Command::First => "first",
+            Command::Last => "last",
+            Command::ScrollLeft => "scroll_left",
+            Command::ScrollRight => "scroll_right",
+            Command::JumpToPid => "jump_to_pid",
+            Command::Filter => "filter",
+            Command::Tag => "tag",
+            Command::TagAll => "tag_all",
+            Command::UntagAll => "untag_all",
+            Command::Explain => "explain",
+            Command::Analyze => "analyze",
+            Command::Copy => "copy",
+            Command::GroupBy => "group_by",
+            Command::Expand => "expand",
+            Command::WaitEvents => "wait_events",
+            Command::History => "history",
+            Command::NarrowWindow => "narrow_window",
+            Command::WidenWindow => "widen_window",
+            Command::Close => "close",
+            Command::NextServer => "next_server",
+            Command::PreviousServer => "previous_server",
+            Command::ToggleTables => "toggle_tables",
+            Command::Setup => "setup",
+            Command::MoveColumnLeft => "move_column_left",
+            Command::MoveColumnRight => "move_column_right",
+            Command::Refresh => "refresh",
+            Command::Cancel => "cancel",
+            Command::Terminate => "terminate",
+            Command::PlayPause => "play_pause",
+            Command::StepForward => "step_forward",
+            Command::StepBackward => "step_backward",
+            Command::SeekForward => "seek_forward",
+            Command::SeekBackward => "seek_backward",
+            Command::SeekStart => "seek_start",
+            Command::SeekEnd => "seek_end",
+        }
+    }
+
+    pub fn description(self) -> &'static str {
+        match self {
+            Command::Help => "show this help",
+            Command::Quit => "quit",
+            Command::Down => "select the next backend",
+            Command::Up => "select the previous backend",
+            Command::PageDown => "select the backend a page below",
+            Command::PageUp => "select the backend a page above",
+            Command::First => "select the first backend",
+            Command::Last => "select the last backend",
+            Command::ScrollLeft => "scroll the columns to the left, or narrow a column",
+            Command::ScrollRight => {
+                "scroll the columns to the right then the last one, or widen a column"
+            }
+            Command::JumpToPid => "select the backend of a pid",
+            Command::Filter => "only show the backends containing some text",
+            Command::Tag => "tag or untag the selected backend, or show or hide a column",
+            Command::TagAll => "tag every backend shown",
+            Command::UntagAll => "untag every backend",
+            Command::Explain => "show the query and the plan of the selected backend",
+            Command::Analyze => "run the query of the plan shown with EXPLAIN ANALYZE",
+            Command::Copy => "copy the query, the pid or a termination statement of the backend",
+            Command::GroupBy => "group the backends by the next attribute, or stop grouping them",
+            Command::Expand => {
+                "list the backends of the selected group or the groups again, save the columns"
+            }
+            Command::WaitEvents => "show or hide the wait events of the active backends",
+            Command::History => {
+                "show the top queries, users and wait events sampled over a time window"
+            }
+            Command::NarrowWindow => "narrow the time window of the history",
+            Command::WidenWindow => "widen the time window of the history",
+            Command::Close => "close the plan or the history, discard the columns chosen",
+            Command::NextServer => "switch to the next server",
+            Command::PreviousServer => "switch to the previous server",
+            Command::ToggleTables => "switch between the activities and the tables",
+            Command::Setup => "choose the columns of the view",
+            Command::MoveColumnLeft => "move the column chosen in the setup to the left",
+            Command::MoveColumnRight => "move the column chosen in the setup to the right",
+            Command::Refresh => "refresh now",
+            Command::Cancel => "cancel the queries of the tagged or selected backends",
+            Command::Terminate => "terminate the tagged or selected backends",
+            Command::PlayPause => "pause or resume the replay",
+            Command::StepForward => "next snapshot",
+            Command::StepBackward => "previous snapshot",
+            Command::SeekForward => "skip 10 snapshots forward",
+            Command::SeekBackward => "skip 10 snapshots backward",
+            Command::SeekStart => "first snapshot",
+            Command::SeekEnd => "last snapshot",
+        }
+    }
+
+    /// Label of the command in the footer, which only lists the most useful ones.
+    pub fn label(self) -> Option<&'static str> {
+        match self {
+            Command::Help => Some("Help"),
+            Command::Quit => Some("Quit"),
+            Command::NextServer => Some("Server"),
+            Command::ToggleTables => Some("Tables"),
+            Command::Setup => Some("Setup"),
+            Command::Refresh => Some("Refresh"),
+            Command::Filter => Some("Filter"),
+            Command::Tag => Some("Tag"),
+            Command::GroupBy => Some("Group"),
+            Command::WaitEvents => Some("Waits"),
+            Command::History => Some("History"),
+            Command::Cancel => Some("Cancel"),
+            Command::Terminate => Some("Kill"),
+            Command::PlayPause => Some("Play"),
+            _ => None,
+        }
+    }
+
+    /// Whether the command changes the state of the servers, and is disabled in read-only mode.
+    pub fn is_action(self) -> bool {
+        matches!(
+            self,
+            Command::Cancel | Command::Terminate | Command::Analyze
+        )
+    }
+
+    /// Whether the command controls the playback of a recording.
+    pub fn is_replay_only(self) -> bool {
+        matches!(
+            self,
+            Command::PlayPause
+                | Command::StepForward
+                | Command::StepBackward
+                | Command::SeekForward
+                | Command::SeekBackward
+                | Command::SeekStart
+                | Command::SeekEnd
+        )
+    }
+
+    fn default_keys(self) -> &'static [Key] {
+        match self {
+            Command::Help => &[Key::Char('?'), Key::F(1)],
+            Command::Quit => &[Key::Char('q'), Key::Ctrl('c')],
+            Command::Down => &[Key::Down],
+            Command::Up => &[Key::Up],
+            Command::PageDown => &[Key::PageDown],
+            Command::PageUp => &[Key::PageUp],
+            Command::First => &[Key::Home],
+            Command::Last => &[Key::End],
+            Command::ScrollLeft => &[Key::Left],
+            Command::ScrollRight => &[Key::Right],
+            Command::JumpToPid => &[Key::Char('p')],
+            Command::Filter => &[Key::Char('/')],
+            Command::Tag => &[Key::Char(' ')],
+            Command::TagAll => &[Key::Char('a')],
+            Command::UntagAll => &[Key::Char('u')],
+            Command::Explain => &[Key::Char('e')],
+            Command::Analyze => &[Key::Char('A')],
+            Command::Copy => &[Key::Char('y')],
+            Command::GroupBy => &[Key::Char('g')],
+            Command::Expand => &[Key::Char('\n')],
+            Command::WaitEvents => &[Key::Char('w')],
+            Command::History => &[Key::Char('h')],
+            Command::NarrowWindow => &[Key::Char('-')],
+            Command::WidenWindow => &[Key::Char('+'), Key::Char('=')],
+            Command::Close => &[Key::Esc],
+            Command::NextServer => &[Key::Char('\t')],
+            Command::PreviousServer => &[Key::BackTab],
+            Command::ToggleTables => &[Key::Char('t')],
+            Command::Setup => &[Key::F(2), Key::Char('S')],
+            Command::MoveColumnLeft => &[Key::Char('<')],
+            Command::MoveColumnRight => &[Key::Char('>')],
+            Command::Refresh => &[Key::Ctrl('r')],
+            Command::Cancel => &[Key::Char('c')],
+            Command::Terminate => &[Key::Char('k')],
+            Command::PlayPause => &[Key::Char(' ')],
+            Command::StepForward => &[Key::Char('.')],
+            Command::StepBackward => &[Key::Char(',')],
+            Command::SeekForward => &[Key::Char(']')],
+            Command::SeekBackward => &[Key::Char('[')],
+            Command::SeekStart => &[Key::Char('<')],
+            Command::SeekEnd => &[Key::Char('>')],
+        }
+    }
+}
+
+/// Bindings of the keys to the commands, the single source of the key handling, the help
+/// screen and the footer.
+pub struct Keymap {
+    bindings: Vec<(Key, Command)>,
+}
+
+impl Keymap {
+    /// Overrides the default keys of the commands listed in the configuration file. Unknown
+    /// commands or keys, commands left without keys and keys bound to several commands, whether
+    /// in the file or by default to a command not listed there, are rejected, except that a
+    /// playback command may share its keys with another command, taking precedence when
+    /// replaying.
+    pub fn new(settings: &HashMap<String, KeyBindingSettings>) -> Result<Keymap, ConfigError> {
+        let mut bindings: Vec<(Key, Command)> = vec![];
+        for command in Command::ALL.iter().copied() {
+            let names = match settings.get(command.name()) {
+                Some(names) => names.keys(),
+                None => continue,
+            };
+            for name in names {
+                let key = parse_key(name).ok_or_else(|| {
+                    ConfigError::Message(format!(
+                        "invalid key '{}' for {} in the keymap",
+                        name,
+                        command.name()
+                    ))
+                })?;
+                if let Some((_, bound)) = bindings
+                    .iter()
+                    .find(|(bound, other)| *bound == key && conflict(*other, command))
+                {
+                    return Err(ConfigError::Message(format!(
+                        "key {} is bound to both {} and {} in the keymap",
+                        key_name(key),
+                        bound.name(),
+                        command.name()
+                    )));
+                }
+                if let Some(other) = Command::ALL.iter().copied().find(|other| {
+                    !settings.contains_key(other.name())
+                        && other.default_keys().contains(&key)
+                        && conflict(*other, command)
+                }) {
+                    return Err(ConfigError::Message(format!(
+                        "key {} of {} is bound to {} by default, rebind {} as well in the keymap",
+                        key_name(key),
+                        command.name(),
+                        other.name(),
+                        other.name()
+                    )));
+                }
+                bindings.push((key, command));
+            }
+            if names.is_empty() {
+                return Err(ConfigError::Message(format!(
+                    "no key is bound to {} in the keymap",
+                    command.name()
+                )));
+            }
+        }
+        for command in Command::ALL.iter().copied() {
+            if !settings.contains_key(command.name()) {
+                bindings.extend(command.default_keys().iter().map(|key| (*key, command)));
+            }
+        }
+        if let Some(name) = settings
+            .keys()
+            .find(|name| !Command::ALL.iter().any(|command| command.name() == *name))
+        {
+            return Err(ConfigError::Message(format!(
+                "unknown command '{}' in the keymap",
+                name
+            )));
+        }
+        Ok(Keymap { bindings })
+    }
+
+    /// Command bound to the key, playback commands being only available, and preferred, when
+    /// replaying.
+    pub fn command(&self, key: Key, replaying: bool) -> Option<Command> {
+        let find = |replay_only: bool| {
+            self.bindings
+                .iter()
+                .find(|(bound, command)| *bound == key && command.is_replay_only() == replay_only)
+                .map(|(_, command)| *command)
+        };
+        if replaying {
+            find(true).or_else(|| find(false))
+        } else {
+            find(false)
+        }
+    }
+
+    /// Keys bound to the command, leaving out the ones taken by playback commands when replaying.
+    pub fn keys(&self, command: Command, replaying: bool) -> Vec<Key> {
+        self.bindings
+            .iter()
+            .filter(|(key, bound)| {
+                *bound == command && self.command(*key, replaying) == Some(command)
+            })
+            .map(|(key, _)| *key)
+            .collect()
+    }
+}
+
+/// Whether two commands cannot share a key, which playback commands can with the other ones.
+fn conflict(a: Command, b: Command) -> bool {
+    a.is_replay_only() == b.is_replay_only()
+}
+
+/// Parses a key named as by `key_name`, e.g. `j`, `Ctrl-r`, `F1` or `PgDn`. Named keys and
+/// modifiers are case-insensitive.
+pub fn parse_key(name: &str) -> Option<Key> {
+    let mut chars = name.chars();
+    if let (Some(c), None) = (chars.next(), chars.next()) {
+        return Some(Key::Char(c));
+    }
+    let lowercase = name.to_lowercase();
+    let modified = |prefix: &str| {
+        let mut chars = name.get(prefix.len()..)?.chars();
+        match (chars.next(), chars.next()) {
+            (Some(c), None) if lowercase.starts_with(prefix) => Some(c),
+            _ => None,
+        }
+    };
+    if let Some(c) = modified("ctrl-") {
+        return Some(Key::Ctrl(c.to_ascii_lowercase()));
+    }
+    if let Some(c) = modified("alt-") {
+        return Some(Key::Alt(c));
+    }
+    if let Some(n) = lowercase.strip_prefix('f').and_then(|n| n.parse().ok()) {
+        return Some(Key::F(n));
+    }
+    let key = match lowercase.as_str() {
+        "space" => Key::Char(' '),
+        "tab" => Key::Char('\t'),
+        "enter" => Key::Char('\n'),
+        "shift-tab" | "backtab" => Key::BackTab,
+        "up" => Key::Up,
+        "down" => Key::Down,
+        "left" => Key::Left,
+        "right" => Key::Right,
+        "pgup" | "pageup" => Key::PageUp,
+        "pgdn" | "pagedown" => Key::PageDown,
+        "home" => Key::Home,
+        "end" => Key::End,
+        "insert" => Key::Insert,
+        "delete" => Key::Delete,
+        "backspace" => Key::Backspace,
+        "esc" => Key::Esc,
+        _ => return None,
+    };
+    Some(key)
+}
+
+/// Name of the key, as shown in the help screen and the footer.
+pub fn key_name(key: Key) -> String {
+    match key {
+        Key::Char(' ') => String::from("Space"),
+        Key::Char('\t') => String::from("Tab"),
+        Key::Char('\n') => String::from("Enter"),
+        Key::Char(c) => c.to_string(),
+        Key::Ctrl(c) => format!("Ctrl-{}", c),
+        Key::Alt(c) => format!("Alt-{}", c),
+        Key::F(n) => format!("F{}", n),
+        Key::BackTab => String::from("Shift-Tab"),
+        Key::Up => String::from("Up"),
+        Key::Down => String::from("Down"),
+        Key::Left => String::from("Left"),
+        Key::Right => String::from("Right"),
+        Key::PageUp => String::from("PgUp"),
+        Key::PageDown => String::from("PgDn"),
+        Key::Home => String::from("Home"),
+        Key::End => String::from("End"),
+        Key::Insert => String::from("Insert"),
+        Key::Delete => String::from("Delete"),
+        Key::Backspace => String::from("Backspace"),
+        Key::Esc => String::from("Esc"),
+        _ => String::from("?"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use termion::event::Key;
+
+    use super::{key_name, parse_key, Command, KeyBindingSettings, Keymap};
+
+    fn keymap(bindings: &[(&str, &[&str])]) -> Result<Keymap, String> {
+        let settings = bindings
+            .iter()
+            .map(|(command, keys)| {
+                let keys = keys.iter().map(|key| key.to_string()).collect();
+                (command.to_string(), KeyBindingSettings::Many(keys))
+            })
+            .collect::<HashMap<_, _>>();
+        Keymap::new(&settings).map_err(|err| err.to_string())
+    }
+
+    #[test]
+    fn parse_key_reads_named_and_modified_keys() {
+        assert_eq!(parse_key("j"), Some(Key::Char('j')));
+        assert_eq!(parse_key("F1"), Some(Key::F(1)));
+        assert_eq!(parse_key("f12"), Some(Key::F(12)));
+        assert_eq!(parse_key("Ctrl-r"), Some(Key::Ctrl('r')));
+        assert_eq!(parse_key("ctrl-R"), Some(Key::Ctrl('r')));
+        assert_eq!(parse_key("Alt-x"), Some(Key::Alt('x')));
+        assert_eq!(parse_key("Shift-Tab"), Some(Key::BackTab));
+        assert_eq!(parse_key("backtab"), Some(Key::BackTab));
+        assert_eq!(parse_key("Space"), Some(Key::Char(' ')));
+        assert_eq!(parse_key("PgDn"), Some(Key::PageDown));
+    }
+
+    #[test]
+    fn parse_key_rejects_invalid_names() {
+        for name in &[
+            "",
+            "Ctrl-",
+            "Ctrl-ab",
+            "Fx",
+            "Shift-a",
+            "Hyper-x",
+            "PageDownward",
+        ] {
+            assert_eq!(parse_key(name), None, "{}", name);
+        }
+    }
+
+    #[test]
+    fn key_names_are_parsed_back() {
+        let keys = [
+            Key::Char('?'),
+            Key::Char(' '),
+            Key::Char('\t'),
+            Key::Char('\n'),
+            Key::Ctrl('r'),
+            Key::Alt('x'),
+            Key::F(2),
+            Key::BackTab,
+            Key::PageUp,
+            Key::Esc,
+        ];
+        for key in keys.iter().copied() {
+            assert_eq!(parse_key(&key_name(key)), Some(key));
+        }
+    }
+
+    #[test]
+    fn bindings_replace_the_default_keys() {
+        let keymap = keymap(&[("down", &["j", "Down"])]).unwrap();
+        assert_eq!(keymap.command(Key::Char('j'), false), Some(Command::Down));
+        assert_eq!(
+            keymap.keys(Command::Down, false),
+            vec![Key::Char('j'), Key::Down]
+        );
+        assert_eq!(keymap.command(Key::Up, false), Some(Command::Up));
+    }
+
+    #[test]
+    fn conflicting_bindings_are_rejected() {
+        let err = keymap(&[("down", &["j"]), ("up", &["j"])]).err().unwrap();
+        assert!(err.contains("bound to both"), "{}", err);
+        let err = keymap(&[("down", &["j", "j"])]).err().unwrap();
+        assert!(err.contains("bound to both"), "{}", err);
+    }
+
+    #[test]
+    fn keys_taken_from_the_defaults_are_rejected() {
+        let err = keymap(&[("refresh", &["k"])]).err().unwrap();
+        assert!(err.contains("bound to terminate by default"), "{}", err);
+        assert!(keymap(&[("terminate", &["K"]), ("refresh", &["k"])]).is_ok());
+    }
+
+    #[test]
+    fn invalid_settings_are_rejected() {
+        assert!(keymap(&[("down", &["Hyper-j"])]).is_err());
+        assert!(keymap(&[("down", &[])]).is_err());
+        assert!(keymap(&[("jump", &["j"])]).is_err());
+    }
+
+    #[test]
+    fn playback_commands_take_precedence_only_when_replaying() {
+        let keymap = keymap(&[]).unwrap();
+        assert_eq!(keymap.command(Key::Char(' '), false), Some(Command::Tag));
+        assert_eq!(
+            keymap.command(Key::Char(' '), true),
+            Some(Command::PlayPause)
+        );
+        assert_eq!(
+            keymap.command(Key::Char('<'), false),
+            Some(Command::MoveColumnLeft)
+        );
+        assert_eq!(
+            keymap.command(Key::Char('<'), true),
+            Some(Command::SeekStart)
+        );
+        assert_eq!(keymap.command(Key::Char('.'), false), None);
+        assert!(keymap.keys(Command::Tag, true).is_empty());
+        assert!(keymap.keys(Command::StepForward, false).is_empty());
+    }
+
+    #[test]
+    fn navigation_keys_are_kept_when_replaying() {
+        let keymap = keymap(&[]).unwrap();
+        let navigation = [
+            Command::Down,
+            Command::Up,
+            Command::PageDown,
+            Command::PageUp,
+            Command::First,
+            Command::Last,
+            Command::ScrollLeft,
+            Command::ScrollRight,
+        ];
+        for command in navigation.iter().copied() {
+            assert_eq!(
+                keymap.keys(command, true),
+                keymap.keys(command, false),
+                "{}",
+                command.name()
+            );
+        }
+    }
+
+    #[test]
+    fn playback_commands_may_share_keys() {
+        let keymap = keymap(&[("step_forward", &["n"]), ("filter", &["n"])]).unwrap();
+        assert_eq!(keymap.command(Key::Char('n'), false), Some(Command::Filter));
+        assert_eq!(
+            keymap.command(Key::Char('n'), true),
+            Some(Command::StepForward)
+        );
+    }
+}
@@ -2,25 +2,46 @@
 extern crate serde_derive;
 
 use std::env;
+use std::time::Duration;
 
-use tokio_postgres::{Client, NoTls};
+use clap::value_t;
 
 use cli::parse_args;
 
+mod alert;
+mod audit;
 mod banner;
+mod batch;
+mod capabilities;
 mod cli;
+mod clipboard;
+mod columns;
 mod db;
 mod error;
 mod event;
+mod explain;
+mod exporter;
+mod format;
+mod group;
+mod history;
+mod keymap;
+mod recording;
+mod server;
 mod settings;
+mod source;
+mod theme;
 mod ui;
+mod wait;
+mod watchdog;
 
 /// A `Result` alias where the `Err` case is `CliError`.
 pub type CliResult<T> = std::result::Result<T, error::CliError>;
 
 pub struct Context {
-    client: Client,
-    events: event::Events,
+    servers: Vec<server::Server>,
+    refresh_rate: Duration,
+    /// Whether state-changing actions on the servers are disabled.
+    read_only: bool,
 }
 
 #[tokio::main] // By default, tokio_postgres uses the tokio crate as its runtime.
@@ -31,6 +52,44 @@ async fn main() -> CliResult<()> {
         eprintln!("configuration error: {:}", err);
         std::process::exit(exitcode::CONFIG);
     });
+    let config = settings::Settings::new(args.value_of("config_file")).unwrap_or_else(|err| {
+        eprintln!("configuration error: {:}", err);
+        std::process::exit(exitcode::CONFIG);
+    });
+    let alerts = alert::Alerts::new(&config.alerts).unwrap_or_else(|err| {
+        eprintln!("configuration error: {:}", err);
+        std::process::exit(exitcode::CONFIG);
+    });
+    let keymap = keymap::Keymap::new(&config.keymap).unwrap_or_else(|err| {
+        eprintln!("configuration error: {:}", err);
+        std::process::exit(exitcode::CONFIG);
+    });
+    let layouts = columns::Layouts::new(&config.columns).unwrap_or_else(|err| {
+        eprintln!("configuration error: {:}", err);
+        std::process::exit(exitcode::CONFIG);
+    });
+    // https://no-color.org: set to any non-empty value
+    let no_color =
+        args.is_present("no_color") || matches!(env::var_os("NO_COLOR"), Some(v) if !v.is_empty());
+    let theme = theme::Theme::new(config.theme.as_deref(), no_color).unwrap_or_else(|err| {
+        eprintln!("configuration error: {:}", err);
+        std::process::exit(exitcode::CONFIG);
+    });
+    let history = history::History::new(&config.history).unwrap_or_else(|err| {
+        eprintln!("configuration error: {:}", err);
+        std::process::exit(exitcode::CONFIG);
+    });
+    let ui_options = ui::Options {
+        keymap,
+        layouts,
+        theme,
+        history,
+        audit_log: config.audit_log.as_deref(),
+        config_path: match args.value_of("config_file") {
+            Some(name) => settings::Settings::resolve_path(name),
+            None => settings::Settings::default_path(),
+        },
+    };
     // cli args have precedence over env config
     if let Some(host) = args.value_of("host") {
         settings.pghost = Some(String::from(host));
@@ -44,20 +103,77 @@ async fn main() -> CliResult<()> {
     if let Some(user) = args.value_of("user") {
         settings.pguser = Some(String::from(user));
     }
+    let delay = value_t!(args, "delay", f64).unwrap_or_else(|err| err.exit());
+    if !delay.is_finite() || delay <= 0.0 {
+        eprintln!(
+            "invalid delay: {}, expected a positive number of seconds",
+            delay
+        );
+        std::process::exit(exitcode::USAGE);
+    }
+    let refresh_rate = Duration::from_secs_f64(delay);
+    let output_format = if args.is_present("output") {
+        value_t!(args, "output", batch::OutputFormat).unwrap_or_else(|err| err.exit())
+    } else {
+        batch::OutputFormat::Text
+    };
+    let iterations = if args.is_present("iterations") {
+        Some(value_t!(args, "iterations", u64).unwrap_or_else(|err| err.exit()))
+    } else {
+        None
+    };
 
-    // Connect to the database.
-    let (client, connection) =
-        tokio_postgres::connect(&settings.get_kv_connection_string(), NoTls).await?;
-
-    // The connection object performs the actual communication with the database,
-    // so spawn it off to run on its own.
-    tokio::spawn(async move {
-        if let Err(e) = connection.await {
-            eprintln!("connection error: {}", e);
-        }
-    });
+    if let Some(path) = args.value_of("replay") {
+        let replay = recording::Replay::load(path, refresh_rate)?;
+        return ui::start_ui(source::Source::Replay(replay), alerts, ui_options).await;
+    }
+    let recorder = args
+        .value_of("record")
+        .map(recording::Recorder::create)
+        .transpose()?;
 
-    let events = event::Events::new();
-    let ctx = Context { client, events };
-    ui::start_ui(ctx).await
+    let servers = match args.values_of("server") {
+        Some(targets) => targets
+            .map(|target| match config.profile(target) {
+                Some(profile) => server::Server::new(
+                    Some(target),
+                    &settings.with_profile(profile).get_kv_connection_string(),
+                ),
+                None => server::Server::new(None, target),
+            })
+            .collect::<CliResult<Vec<_>>>()?,
+        None => vec![server::Server::new(
+            None,
+            &settings.get_kv_connection_string(),
+        )?],
+    };
+    let read_only = args.is_present("read_only") || config.read_only;
+    if read_only && args.is_present("watchdog") {
+        eprintln!("the watchdog cannot run in read-only mode");
+        std::process::exit(exitcode::USAGE);
+    }
+    let mut ctx = Context {
+        servers,
+        refresh_rate,
+        read_only,
+    };
+    // Connect early so that a lone server being unreachable is reported before anything is drawn.
+    if let [server] = ctx.servers.as_mut_slice() {
+        server.connect().await?;
+    }
+    if args.is_present("watchdog") {
+        let watchdog = watchdog::Watchdog::new(&config.watchdog, args.is_present("dry_run"))
+            .unwrap_or_else(|err| {
+                eprintln!("configuration error: {:}", err);
+                std::process::exit(exitcode::CONFIG);
+            });
+        let audit = audit::AuditLog::open(config.audit_log.as_deref())?;
+        watchdog::run(ctx, watchdog, audit).await
+    } else if let Some(address) = args.value_of("exporter") {
+        exporter::run(ctx, address).await
+    } else if args.is_present("batch") {
+        batch::run(ctx, recorder, alerts, iterations, output_format).await
+    } else {
+        ui::start_ui(source::Source::Live { ctx, recorder }, alerts, ui_options).await
+    }
 }
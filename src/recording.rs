@@ -0,0 +1,242 @@
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+
+use crate::db::Snapshot;
+use crate::error::CliError;
+use crate::server::ServerStatus;
+use crate::CliResult;
+
+/// Snapshot as written to a recording, along with the refresh it was taken at.
+#[derive(Serialize)]
+struct Recorded<'a> {
+    refreshed_at: DateTime<Utc>,
+    #[serde(flatten)]
+    snapshot: &'a Snapshot,
+}
+
+/// Snapshot read back from a recording, the refresh being unknown in older recordings.
+#[derive(Deserialize)]
+struct Loaded {
+    #[serde(default)]
+    refreshed_at: Option<DateTime<Utc>>,
+    #[serde(flatten)]
+    snapshot: Snapshot,
+}
+
+/// Appends snapshots to a file, one JSON document per line, so that a recording interrupted
+/// abruptly stays readable up to its last complete snapshot.
+pub struct Recorder {
+    writer: BufWriter<File>,
+}
+
+impl Recorder {
+    pub fn create<P: AsRef<Path>>(path: P) -> CliResult<Recorder> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(Recorder {
+            writer: BufWriter::new(file),
+        })
+    }
+
+    /// Records the snapshots of every server taken at a refresh, marked with the time of the
+    /// refresh so that they are played back together.
+    pub fn record<'a, I>(&mut self, snapshots: I) -> CliResult<()>
+    where
+        I: IntoIterator<Item = &'a Snapshot>,
+    {
+        let refreshed_at = Utc::now();
+        for snapshot in snapshots {
+            let recorded = Recorded {
+                refreshed_at,
+                snapshot,
+            };
+            serde_json::to_writer(&mut self.writer, &recorded)?;
+            self.writer.write_all(b"\n")?;
+        }
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+/// Snapshots loaded from a recording, played back at the refresh rate.
+pub struct Replay {
+    /// Snapshots of every server recorded at each refresh.
+    frames: Vec<Vec<Snapshot>>,
+    position: usize,
+    playing: bool,
+    pub(crate) refresh_rate: Duration,
+}
+
+/// Groups the snapshots taken at the same refresh. Older recordings do not tell the refreshes
+/// apart, a frame then ending when a server shows up for the second time.
+fn frames(snapshots: Vec<Loaded>) -> Vec<Vec<Snapshot>> {
+    let mut frames: Vec<(Option<DateTime<Utc>>, Vec<Snapshot>)> = vec![];
+    for Loaded {
+        refreshed_at,
+        snapshot,
+    } in snapshots
+    {
+        match frames.last_mut() {
+            Some((at, frame))
+                if *at == refreshed_at
+                    && (refreshed_at.is_some()
+                        || frame.iter().all(|s| s.server != snapshot.server)) =>
+            {
+                frame.push(snapshot)
+            }
+            _ => frames.push((refreshed_at, vec![snapshot])),
+        }
+    }
+    frames.into_iter().map(|(_, frame)| frame).collect()
+}
+
+impl Replay {
+    pub fn load<P: AsRef<Path>>(path: P, refresh_rate: Duration) -> CliResult<Replay> {
+        let reader = BufReader::new(File::open(path)?);
+        let lines = reader
+            .lines()
+            .filter(|line| !matches!(line, Ok(line) if line.trim().is_empty()))
+            .collect::<Result<Vec<_>, _>>()?;
+        let mut snapshots: Vec<Loaded> = Vec::with_capacity(lines.len());
+        for (i, line) in lines.iter().enumerate() {
+            match serde_json::from_str(line) {
+                Ok(snapshot) => snapshots.push(snapshot),
+                // the recording was interrupted in the middle of a write
+                Err(_) if i == lines.len() - 1 => break,
+                Err(err) => return Err(err.into()),
+            }
+        }
+        if snapshots.is_empty() {
+            return Err(CliError::EmptyRecording);
+        }
+        Ok(Replay {
+            frames: frames(snapshots),
+            position: 0,
+            playing: true,
+            refresh_rate,
+        })
+    }
+
+    pub fn current(&self) -> &[Snapshot] {
+        &self.frames[self.position]
+    }
+
+    /// The current snapshots, as if they had just been taken.
+    pub fn statuses(&self) -> Vec<ServerStatus> {
+        self.current()
+            .iter()
+            .map(|snapshot| ServerStatus {
+                name: snapshot.server.clone(),
+                snapshot: Ok(snapshot.clone()),
+            })
+            .collect()
+    }
+
+    /// Position of the current snapshot, starting at 1.
+    pub fn position(&self) -> usize {
+        self.position + 1
+    }
+
+    pub fn len(&self) -> usize {
+        self.frames.len()
+    }
+
+    pub fn is_playing(&self) -> bool {
+        self.playing
+    }
+
+    pub fn toggle_pause(&mut self) {
+        self.playing = !self.playing;
+    }
+
+    /// Advances playback by one snapshot, pausing once the end of the recording is reached.
+    pub fn tick(&mut self) {
+        if self.playing {
+            self.seek(1);
+            self.playing = self.position + 1 < self.frames.len();
+        }
+    }
+
+    /// Moves by `offset` snapshots, clamped to the bounds of the recording.
+    pub fn seek(&mut self, offset: isize) {
+        let last = self.frames.len() - 1;
+        self.position = if offset < 0 {
+            self.position.saturating_sub(offset.unsigned_abs())
+        } else {
+            (self.position + offset as usize).min(last)
+        };
+    }
+
+    pub fn seek_start(&mut self) {
+        self.position = 0;
+    }
+
+    pub fn seek_end(&mut self) {
+        self.position = self.frames.len() - 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::{frames, Loaded};
+
+    fn loaded(server: &str, refreshed_at: Option<&str>) -> Loaded {
+        let mut snapshot = json!({
+            "server": server,
+            "timestamp": "2024-01-01T12:00:00Z",
+            "system_info": {
+                "version": "PostgreSQL 16",
+                "start_time": "2024-01-01T00:00:00Z",
+                "sampled_at": "2024-01-01T12:00:00Z",
+                "nb_of_conn": 1
+            },
+            "activities": []
+        });
+        if let Some(refreshed_at) = refreshed_at {
+            snapshot["refreshed_at"] = json!(refreshed_at);
+        }
+        serde_json::from_value(snapshot).unwrap()
+    }
+
+    fn servers(frames: &[Vec<crate::db::Snapshot>]) -> Vec<Vec<&str>> {
+        frames
+            .iter()
+            .map(|frame| frame.iter().map(|s| s.server.as_str()).collect())
+            .collect()
+    }
+
+    #[test]
+    fn frames_are_the_refreshes() {
+        let first = Some("2024-01-01T12:00:00Z");
+        let second = Some("2024-01-01T12:00:02Z");
+        // the replica missed the first refresh
+        let frames = frames(vec![
+            loaded("primary", first),
+            loaded("primary", second),
+            loaded("replica", second),
+        ]);
+        assert_eq!(
+            servers(&frames),
+            vec![vec!["primary"], vec!["primary", "replica"]]
+        );
+    }
+
+    #[test]
+    fn frames_of_older_recordings_end_with_a_repeated_server() {
+        let frames = frames(vec![
+            loaded("primary", None),
+            loaded("replica", None),
+            loaded("primary", None),
+            loaded("primary", Some("2024-01-01T12:00:00Z")),
+        ]);
+        assert_eq!(
+            servers(&frames),
+            vec![vec!["primary", "replica"], vec!["primary"], vec!["primary"]]
+        );
+    }
+}
@@ -0,0 +1,348 @@
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use futures_util::future::join_all;
+use tokio_postgres::config::Host;
+use tokio_postgres::{Client, Config, NoTls};
+
+use crate::capabilities::Capabilities;
+use crate::db::{
+    get_active_sessions, get_capabilities, get_databases, get_lock_waits, get_snapshot, get_tables,
+    PGStatActivity, PGStatUserTable, Snapshot,
+};
+use crate::error::CliError;
+use crate::CliResult;
+
+/// Bounds the time a dashboard refresh can be held by a server which went away.
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+/// Bounds the time a dashboard refresh can be held by a server which stopped answering.
+const SNAPSHOT_TIMEOUT: Duration = Duration::from_secs(15);
+/// Connections kept to the databases of an instance besides the main one, at most, so that
+/// clusters with many databases are not left short of `max_connections`.
+const MAX_DATABASE_CONNECTIONS: usize = 16;
+/// Delay before connecting again to a database which refused the connection, doubled at each
+/// failure up to `MAX_RETRY_DELAY`.
+const RETRY_DELAY: Duration = Duration::from_secs(30);
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(600);
+
+async fn open(config: &Config) -> CliResult<Client> {
+    let (client, connection) = config.connect(NoTls).await?;
+    // The connection object performs the actual communication with the database,
+    // so spawn it off to run on its own.
+    tokio::spawn(async move {
+        if let Err(e) = connection.await {
+            eprintln!("connection error: {}", e);
+        }
+    });
+    Ok(client)
+}
+
+/// Database the connection failed to, not tried again before `retry_at`.
+struct Failure {
+    retry_at: Instant,
+    delay: Duration,
+    error: String,
+}
+
+/// A monitored server, reconnected to whenever its connection is lost.
+pub struct Server {
+    pub(crate) name: String,
+    config: Config,
+    connection: Option<(Client, Capabilities)>,
+    /// Connections to every database of the instance, kept between refreshes for the views
+    /// covering all of them.
+    databases: HashMap<String, Client>,
+    /// Databases of the pool the connection failed to, by name.
+    failures: HashMap<String, Failure>,
+}
+
+impl Server {
+    /// Parses a libpq connection string, the server is named after its address unless a name
+    /// (e.g. the one of its profile) is given.
+    pub fn new(name: Option<&str>, connection_string: &str) -> CliResult<Server> {
+        let mut config = connection_string.parse::<Config>()?;
+        config.connect_timeout(CONNECT_TIMEOUT);
+        let name = match name {
+            Some(name) => String::from(name),
+            None => Server::default_name(&config),
+        };
+        Ok(Server {
+            name,
+            config,
+            connection: None,
+            databases: HashMap::new(),
+            failures: HashMap::new(),
+        })
+    }
+
+    /// `host:port/dbname`, the host being `local` for unix sockets.
+    fn default_name(config: &Config) -> String {
+        let host = match config.get_hosts().first() {
+            Some(Host::Tcp(host)) => host.clone(),
+            _ => String::from("local"),
+        };
+        let mut name = match config.get_ports().first() {
+            Some(port) => format!("{}:{}", host, port),
+            None => host,
+        };
+        if let Some(dbname) = config.get_dbname() {
+            name = format!("{}/{}", name, dbname);
+        }
+        name
+    }
+
+    /// Role pgtop connects as.
+    pub fn role(&self) -> Option<&str> {
+        self.config.get_user()
+    }
+
+    /// Capabilities of the server, unknown until the first successful connection.
+    pub fn capabilities(&self) -> Option<&Capabilities> {
+        self.connection
+            .as_ref()
+            .map(|(_, capabilities)| capabilities)
+    }
+
+    /// Returns the client along with the server capabilities, (re)connecting if needed.
+    pub async fn connect(&mut self) -> CliResult<(&Client, &Capabilities)> {
+        if !matches!(&self.connection, Some((client, _)) if !client.is_closed()) {
+            let client = open(&self.config).await?;
+            let capabilities = get_capabilities(&client).await?;
+            self.connection = Some((client, capabilities));
+        }
+        let (client, capabilities) = self.connection.as_ref().expect("connected above");
+        Ok((client, capabilities))
+    }
+
+    /// Database of the main connection, named after the user when unset as libpq does.
+    fn main_database(&self) -> Option<&str> {
+        self.config.get_dbname().or_else(|| self.config.get_user())
+    }
+
+    fn database_config(&self, dbname: &str) -> Config {
+        let mut config = self.config.clone();
+        config.dbname(dbname);
+        config
+    }
+
+    /// Statistics of the tables of every database accepting connections, along with why some
+    /// databases could not be read. The main database is read through the main connection and
+    /// the others through a pool synced with `pg_database`, so that dropped databases release
+    /// their connection. Databases refusing the connection are only tried again after a delay.
+    async fn tables(&mut self) -> CliResult<(Vec<PGStatUserTable>, Vec<String>)> {
+        let (client, _) = self.connect().await?;
+        let databases = get_databases(client).await?;
+        let main = self.main_database().map(String::from);
+        let mut errors = vec![];
+        let mut pooled = databases
+            .iter()
+            .filter(|dbname| Some(*dbname) != main.as_ref())
+            .cloned()
+            .collect::<Vec<_>>();
+        if pooled.len() > MAX_DATABASE_CONNECTIONS {
+            errors.push(format!(
+                "{} databases left out past {} connections",
+                pooled.len() - MAX_DATABASE_CONNECTIONS,
+                MAX_DATABASE_CONNECTIONS
+            ));
+            pooled.truncate(MAX_DATABASE_CONNECTIONS);
+        }
+        self.databases
+            .retain(|dbname, client| pooled.contains(dbname) && !client.is_closed());
+        self.failures.retain(|dbname, _| pooled.contains(dbname));
+
+        let now = Instant::now();
+        let missing = pooled
+            .iter()
+            .filter(|dbname| {
+                !self.databases.contains_key(*dbname)
+                    && !matches!(self.failures.get(*dbname), Some(failure) if failure.retry_at > now)
+            })
+            .cloned()
+            .collect::<Vec<_>>();
+        let configs = missing
+            .iter()
+            .map(|dbname| self.database_config(dbname))
+            .collect::<Vec<_>>();
+        let opened = join_all(configs.iter().map(open)).await;
+        for (dbname, result) in missing.into_iter().zip(opened) {
+            match result {
+                Ok(client) => {
+                    self.failures.remove(&dbname);
+                    self.databases.insert(dbname, client);
+                }
+                Err(err) => {
+                    let delay = self.failures.get(&dbname).map_or(RETRY_DELAY, |failure| {
+                        (failure.delay * 2).min(MAX_RETRY_DELAY)
+                    });
+                    let failure = Failure {
+                        retry_at: now + delay,
+                        delay,
+                        error: err.to_string(),
+                    };
+                    self.failures.insert(dbname, failure);
+                }
+            }
+        }
+
+        let mut clients = vec![];
+        if let (Some(main), Some((client, _))) = (&main, &self.connection) {
+            if databases.contains(main) {
+                clients.push((main.as_str(), client));
+            }
+        }
+        for dbname in &pooled {
+            match (self.databases.get(dbname), self.failures.get(dbname)) {
+                (Some(client), _) => clients.push((dbname.as_str(), client)),
+                (None, Some(failure)) => errors.push(format!("{}: {}", dbname, failure.error)),
+                (None, None) => {}
+            }
+        }
+        let results = join_all(
+            clients
+                .iter()
+                .map(|(dbname, client)| async move { (*dbname, get_tables(client).await) }),
+        )
+        .await;
+        let mut tables = vec![];
+        for (dbname, result) in results {
+            match result {
+                Ok(database_tables) => tables.extend(database_tables),
+                Err(err) => errors.push(format!("{}: {}", dbname, err)),
+            }
+        }
+        Ok((tables, errors))
+    }
+
+    /// Returns a client connected to the database, the main one or one of the pool of
+    /// `tables`, making room in the pool if it is full.
+    pub async fn database_client(&mut self, dbname: &str) -> CliResult<&Client> {
+        if self.main_database() == Some(dbname) {
+            let (client, _) = self.connect().await?;
+            return Ok(client);
+        }
+        if matches!(self.databases.get(dbname), Some(client) if client.is_closed()) {
+            self.databases.remove(dbname);
+        }
+        if !self.databases.contains_key(dbname) {
+            let client = open(&self.database_config(dbname)).await?;
+            if self.databases.len() >= MAX_DATABASE_CONNECTIONS {
+                // the tables view connects again to the database evicted when shown
+                if let Some(evicted) = self.databases.keys().next().cloned() {
+                    self.databases.remove(&evicted);
+                }
+            }
+            self.failures.remove(dbname);
+            self.databases.insert(String::from(dbname), client);
+        }
+        Ok(&self.databases[dbname])
+    }
+
+    /// Takes a snapshot, along with the statistics of the tables of every database if asked.
+    pub async fn snapshot(&mut self, with_tables: bool) -> CliResult<Snapshot> {
+        let (client, capabilities) = self.connect().await?;
+        let mut snapshot = get_snapshot(client, capabilities).await?;
+        snapshot.server = self.name.clone();
+        if with_tables {
+            let (tables, errors) = self.tables().await?;
+            snapshot.tables = Some(tables);
+            snapshot.table_errors = errors;
+        }
+        Ok(snapshot)
+    }
+
+    /// Takes a snapshot unless the server does not answer in time, its connections being dropped
+    /// then so that the next snapshot does not queue behind the queries left unanswered.
+    pub async fn snapshot_within(
+        &mut self,
+        timeout: Duration,
+        with_tables: bool,
+    ) -> CliResult<Snapshot> {
+        match tokio::time::timeout(timeout, self.snapshot(with_tables)).await {
+            Ok(result) => result,
+            Err(_) => {
+                self.disconnect();
+                Err(CliError::Timeout { after: timeout })
+            }
+        }
+    }
+
+    /// Counts the backends waiting for a lock within the timeout, disconnecting from a server
+    /// which does not answer in time as `snapshot_within` does.
+    pub async fn lock_waits_within(&mut self, timeout: Duration) -> CliResult<i64> {
+        let lock_waits = async {
+            let (client, _) = self.connect().await?;
+            get_lock_waits(client).await
+        };
+        match tokio::time::timeout(timeout, lock_waits).await {
+            Ok(result) => result,
+            Err(_) => {
+                self.disconnect();
+                Err(CliError::Timeout { after: timeout })
+            }
+        }
+    }
+
+    /// Drops the connections to a server which stopped answering, the next refresh connecting
+    /// again.
+    fn disconnect(&mut self) {
+        self.connection = None;
+        self.databases.clear();
+    }
+
+    /// Lists the active backends, as sampled between the refreshes, within `SNAPSHOT_TIMEOUT`.
+    /// Reconnecting is left to the refreshes: nothing is sampled while the server is
+    /// unreachable, and a server which stops answering is disconnected until then.
+    pub async fn sample(&mut self) -> CliResult<Option<Vec<PGStatActivity>>> {
+        let (client, capabilities) = match &self.connection {
+            Some((client, capabilities)) if !client.is_closed() => (client, capabilities),
+            _ => return Ok(None),
+        };
+        let sessions = get_active_sessions(client, capabilities);
+        match tokio::time::timeout(SNAPSHOT_TIMEOUT, sessions).await {
+            Ok(result) => Ok(Some(result?)),
+            Err(_) => {
+                self.disconnect();
+                Err(CliError::Timeout {
+                    after: SNAPSHOT_TIMEOUT,
+                })
+            }
+        }
+    }
+}
+
+/// Outcome of the last refresh of a server.
+pub struct ServerStatus {
+    pub(crate) name: String,
+    /// The snapshot taken, or why it could not be.
+    pub(crate) snapshot: Result<Snapshot, String>,
+}
+
+/// Takes a snapshot of every server at once, each within `SNAPSHOT_TIMEOUT`. When monitoring a
+/// single server a failure is returned as there would be nothing left to show, with several it
+/// is reported as the server's health.
+pub async fn take_snapshots(
+    servers: &mut [Server],
+    with_tables: bool,
+) -> CliResult<Vec<ServerStatus>> {
+    let single = servers.len() == 1;
+    let snapshots = join_all(
+        servers
+            .iter_mut()
+            .map(|server| server.snapshot_within(SNAPSHOT_TIMEOUT, with_tables)),
+    )
+    .await;
+    let mut statuses = Vec::with_capacity(servers.len());
+    for (server, snapshot) in servers.iter().zip(snapshots) {
+        let snapshot = match snapshot {
+            Ok(snapshot) => Ok(snapshot),
+            Err(err) if !single => Err(err.to_string()),
+            Err(err) => return Err(err),
+        };
+        statuses.push(ServerStatus {
+            name: server.name.clone(),
+            snapshot,
+        });
+    }
+    Ok(statuses)
+}
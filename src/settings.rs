@@ -1,16 +1,111 @@
+use std::collections::HashMap;
 use std::env;
+use std::path::PathBuf;
 
-use config::{Config, ConfigError, Environment};
+use config::{Config, ConfigError, Environment, File};
+
+use crate::alert::AlertSettings;
+use crate::columns::ColumnsSettings;
+use crate::history::HistorySettings;
+use crate::keymap::KeyBindingSettings;
+use crate::watchdog::WatchdogSettings;
+
+/// Extensions of the formats `config` reads the configuration file in.
+const CONFIG_EXTENSIONS: [&str; 6] = ["toml", "json", "yaml", "yml", "hjson", "ini"];
+
+/// Settings read from the configuration file.
+#[derive(Debug, Default, Deserialize)]
+pub(crate) struct Settings {
+    /// Connection profiles, by name.
+    #[serde(default)]
+    pub(crate) servers: HashMap<String, ConnectionSettings>,
+    /// Alert rules, checked against every snapshot.
+    #[serde(default)]
+    pub(crate) alerts: Vec<AlertSettings>,
+    /// Log of the actions taken on backends, `~/.local/share/pgtop/audit.log` by default.
+    pub(crate) audit_log: Option<String>,
+    #[serde(default)]
+    pub(crate) watchdog: WatchdogSettings,
+    /// Keys bound to the commands of the interactive interface, by command name.
+    #[serde(default)]
+    pub(crate) keymap: HashMap<String, KeyBindingSettings>,
+    /// Column layouts of the views of the interactive interface.
+    #[serde(default)]
+    pub(crate) columns: ColumnsSettings,
+    /// Sampling of the active session history of the interactive interface.
+    #[serde(default)]
+    pub(crate) history: HistorySettings,
+    /// Colors of the interactive interface, one of `Theme::NAMES`.
+    pub(crate) theme: Option<String>,
+    /// Disables every state-changing action, as `--read-only` does.
+    #[serde(default)]
+    pub(crate) read_only: bool,
+}
+
+impl Settings {
+    /// Loads the given configuration file, or the default one if it exists.
+    pub fn new(config_file: Option<&str>) -> Result<Self, ConfigError> {
+        let mut settings = Config::default();
+        if let Some(path) = config_file {
+            settings.merge(File::with_name(path))?;
+        } else if let Some(path) = Settings::default_path() {
+            settings.merge(File::from(path).required(false))?;
+        }
+        settings.try_into()
+    }
+
+    /// Connection profile of the given name. Names are matched regardless of case since the
+    /// configuration file is read with its keys lowercased.
+    pub(crate) fn profile(&self, name: &str) -> Option<&ConnectionSettings> {
+        self.servers
+            .iter()
+            .find(|(profile, _)| profile.eq_ignore_ascii_case(name))
+            .map(|(_, profile)| profile)
+    }
+
+    /// File loaded for the name given with `--config`, either the file itself or, as `config`
+    /// looks for it, the one named with the extension of a format. None when several of them
+    /// exist, the one loaded being unknown.
+    pub(crate) fn resolve_path(name: &str) -> Option<PathBuf> {
+        let path = PathBuf::from(name);
+        if path.is_file() {
+            return Some(path);
+        }
+        let mut candidates = CONFIG_EXTENSIONS
+            .iter()
+            .map(|extension| path.with_extension(extension))
+            .filter(|candidate| candidate.is_file());
+        match (candidates.next(), candidates.next()) {
+            (Some(candidate), None) => Some(candidate),
+            _ => None,
+        }
+    }
+
+    /// `~/.config/pgtop/config.yaml`
+    pub(crate) fn default_path() -> Option<PathBuf> {
+        let home = env::var_os("HOME")?;
+        let mut path = PathBuf::from(home);
+        path.extend(&[".config", env!("CARGO_PKG_NAME"), "config.yaml"]);
+        Some(path)
+    }
+}
 
 /// Program settings representation.
 /// https://www.postgresql.org/docs/9.1/libpq-envars.html
-#[derive(Debug, Deserialize)]
+/// Profiles of the configuration file may also use the names of the connection string keywords.
+#[derive(Debug, Clone, Default, Deserialize)]
 pub(crate) struct ConnectionSettings {
+    #[serde(alias = "host")]
     pub(crate) pghost: Option<String>,
+    #[serde(alias = "hostaddr")]
     pub(crate) pghostaddr: Option<String>,
+    #[serde(alias = "port")]
     pub(crate) pgport: Option<String>,
+    #[serde(alias = "dbname")]
     pub(crate) pgdatabase: Option<String>,
+    #[serde(alias = "user")]
     pub(crate) pguser: Option<String>,
+    #[serde(alias = "password")]
     pub(crate) pgpassword: Option<String>,
 }
 
@@ -26,6 +121,27 @@ impl ConnectionSettings {
         settings.try_into()
     }
 
+    /// Returns these settings overridden by the ones set in the profile.
+    pub fn with_profile(&self, profile: &ConnectionSettings) -> ConnectionSettings {
+        ConnectionSettings {
+            pghost: profile.pghost.clone().or_else(|| self.pghost.clone()),
+            pghostaddr: profile
+                .pghostaddr
+                .clone()
+                .or_else(|| self.pghostaddr.clone()),
+            pgport: profile.pgport.clone().or_else(|| self.pgport.clone()),
+            pgdatabase: profile
+                .pgdatabase
+                .clone()
+                .or_else(|| self.pgdatabase.clone()),
+            pguser: profile.pguser.clone().or_else(|| self.pguser.clone()),
+            pgpassword: profile
+                .pgpassword
+                .clone()
+                .or_else(|| self.pgpassword.clone()),
+        }
+    }
+
     /// Generates a Key-Value libpq-style connection string.
     pub fn get_kv_connection_string(&self) -> String {
         let mut kv_connection_string = String::new();
@@ -47,7 +163,6 @@ impl ConnectionSettings {
         if let Some(password) = &self.pgpassword {
             kv_connection_string = format!("password={} {}", password, kv_connection_string);
         }
-        println!("{}", kv_connection_string);
         kv_connection_string
     }
 }
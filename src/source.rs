@@ -0,0 +1,204 @@
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use futures_util::future::join_all;
+
+use crate::audit::{Action, AuditLog, Outcome, REQUESTED};
+use crate::capabilities::Capabilities;
+use crate::db::{explain, get_explain_settings, PGStatActivity};
+use crate::error::CliError;
+use crate::explain::{explain_mode, ExplainMode};
+use crate::recording::{Recorder, Replay};
+use crate::server::{take_snapshots, ServerStatus};
+use crate::{CliResult, Context};
+
+/// Where the snapshots shown by the interactive interface come from.
+pub enum Source {
+    /// Snapshots queried from the server, and optionally recorded.
+    Live {
+        ctx: Context,
+        recorder: Option<Recorder>,
+    },
+    /// Snapshots played back from a recording.
+    Replay(Replay),
+}
+
+impl Source {
+    pub fn refresh_rate(&self) -> Duration {
+        match self {
+            Source::Live { ctx, .. } => ctx.refresh_rate,
+            Source::Replay(replay) => replay.refresh_rate,
+        }
+    }
+
+    /// Capabilities of the nth server, unknown when replaying a recording.
+    pub fn capabilities(&self, server: usize) -> Option<&Capabilities> {
+        match self {
+            Source::Live { ctx, .. } => ctx.servers.get(server)?.capabilities(),
+            Source::Replay(_) => None,
+        }
+    }
+
+    /// Queries a new snapshot of every server, or returns the current ones of a replay.
+    /// Table statistics are costly to gather from every database and only queried on demand.
+    pub async fn fetch(&mut self, with_tables: bool) -> CliResult<Vec<ServerStatus>> {
+        match self {
+            Source::Live { ctx, recorder } => {
+                let statuses = take_snapshots(&mut ctx.servers, with_tables).await?;
+                if let Some(recorder) = recorder {
+                    recorder.record(
+                        statuses
+                            .iter()
+                            .filter_map(|status| status.snapshot.as_ref().ok()),
+                    )?;
+                }
+                Ok(statuses)
+            }
+            Source::Replay(replay) => Ok(replay.statuses()),
+        }
+    }
+
+    /// Whether state-changing actions are disabled, as they are when replaying a recording.
+    pub fn read_only(&self) -> bool {
+        match self {
+            Source::Live { ctx, .. } => ctx.read_only,
+            Source::Replay(_) => true,
+        }
+    }
+
+    /// Cancels or terminates a backend of the nth server, logging the action before taking it
+    /// and its outcome afterwards, failures to connect included. Nothing is done if the log
+    /// cannot be written. Returns the outcome along with its description as written in the log.
+    /// Every action on the servers goes through here so that none escapes the read-only mode.
+    pub async fn act(
+        &mut self,
+        server: usize,
+        action: Action,
+        activity: &PGStatActivity,
+        audit: &mut AuditLog,
+    ) -> CliResult<(Outcome, String)> {
+        let server = match self {
+            Source::Live { ctx, .. } if !ctx.read_only => &mut ctx.servers[server],
+            _ => return Err(CliError::ReadOnly),
+        };
+        let name = server.name.clone();
+        let role = server.role().map(String::from);
+        audit.record(&name, role.as_deref(), action, activity, None, REQUESTED)?;
+        let result = match server.connect().await {
+            Ok((client, _)) => action.apply(client, activity).await,
+            Err(err) => Err(err),
+        };
+        let outcome = action.describe(&result);
+        audit.record(&name, role.as_deref(), action, activity, None, &outcome)?;
+        Ok((Outcome::of(&result), outcome))
+    }
+
+    /// Explains the query of a backend of the nth server, in the database of the backend,
+    /// without running it.
+    pub async fn explain(
+        &mut self,
+        server: usize,
+        activity: &PGStatActivity,
+    ) -> CliResult<(ExplainMode, Vec<String>)> {
+        self.plan(server, activity, false).await
+    }
+
+    /// Runs the query of a backend of the nth server with EXPLAIN ANALYZE, logging the request
+    /// before and its outcome afterwards as `act` does. Disabled in read-only mode.
+    pub async fn analyze(
+        &mut self,
+        server: usize,
+        activity: &PGStatActivity,
+        audit: &mut AuditLog,
+    ) -> CliResult<(ExplainMode, Vec<String>)> {
+        let (name, role) = match self {
+            Source::Live { ctx, .. } if !ctx.read_only => {
+                let server = &ctx.servers[server];
+                (server.name.clone(), server.role().map(String::from))
+            }
+            _ => return Err(CliError::ReadOnly),
+        };
+        let action = Action::Analyze;
+        audit.record(&name, role.as_deref(), action, activity, None, REQUESTED)?;
+        let result = self.plan(server, activity, true).await;
+        let outcome = match &result {
+            Ok(_) => String::from(action.done()),
+            Err(err) => format!("failed: {}", err),
+        };
+        audit.record(&name, role.as_deref(), action, activity, None, &outcome)?;
+        result
+    }
+
+    /// Explains the query of a backend of the nth server in the database of the backend,
+    /// running it if analyzing.
+    async fn plan(
+        &mut self,
+        server: usize,
+        activity: &PGStatActivity,
+        analyze: bool,
+    ) -> CliResult<(ExplainMode, Vec<String>)> {
+        let server = match self {
+            Source::Live { ctx, .. } if analyze && ctx.read_only => return Err(CliError::ReadOnly),
+            Source::Live { ctx, .. } => &mut ctx.servers[server],
+            Source::Replay(_) => {
+                return Err(CliError::Explain {
+                    reason: String::from("no server to plan it when replaying a recording"),
+                })
+            }
+        };
+        let query = activity.query.as_deref().unwrap_or_default();
+        let (client, capabilities) = server.connect().await?;
+        let capabilities = *capabilities;
+        let settings = get_explain_settings(client).await?;
+        let mode = explain_mode(query, analyze, &capabilities, &settings)?;
+        let dbname = activity
+            .datname
+            .as_deref()
+            .ok_or_else(|| CliError::Explain {
+                reason: String::from("the database of the backend is unknown"),
+            })?;
+        let client = server.database_client(dbname).await?;
+        Ok((mode, explain(client, query, mode).await?))
+    }
+
+    /// Bytes of the queries kept by `pg_stat_activity` on the nth server, unknown when replaying
+    /// a recording or when the server cannot be queried.
+    pub async fn query_size(&mut self, server: usize) -> Option<usize> {
+        let server = match self {
+            Source::Live { ctx, .. } => &mut ctx.servers[server],
+            Source::Replay(_) => return None,
+        };
+        let (client, _) = server.connect().await.ok()?;
+        let settings = get_explain_settings(client).await.ok()?;
+        Some(settings.track_activity_query_size)
+    }
+
+    /// Samples the active backends of every server connected to, all at once. The servers which
+    /// cannot be queried, or not in time, are left out, the refreshes reporting why.
+    pub async fn sample(&mut self) -> Vec<(String, DateTime<Utc>, Vec<PGStatActivity>)> {
+        let servers = match self {
+            Source::Live { ctx, .. } => &mut ctx.servers,
+            Source::Replay(_) => return vec![],
+        };
+        let samples = join_all(servers.iter_mut().map(|server| async move {
+            let activities = server.sample().await;
+            (server.name.clone(), Utc::now(), activities)
+        }))
+        .await;
+        samples
+            .into_iter()
+            .filter_map(|(name, sampled_at, activities)| match activities {
+                Ok(Some(activities)) => Some((name, sampled_at, activities)),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Called at every refresh, moves playback forward when replaying.
+    pub async fn tick(&mut self, with_tables: bool) -> CliResult<Vec<ServerStatus>> {
+        if let Source::Replay(replay) = self {
+            replay.tick();
+        }
+        self.fetch(with_tables).await
+    }
+}
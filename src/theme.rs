@@ -0,0 +1,179 @@
+use config::ConfigError;
+use tui::style::{Color, Modifier, Style};
+
+/// Styles of the interactive interface.
+#[derive(Debug, Clone, Copy)]
+pub struct Theme {
+    pub(crate) title: Style,
+    pub(crate) header: Style,
+    pub(crate) selected: Style,
+    pub(crate) alert: Style,
+    /// Backends tagged for a bulk action.
+    pub(crate) tagged: Style,
+    /// State of the backends running a query.
+    pub(crate) active: Style,
+    /// Health of the servers which could be queried.
+    pub(crate) up: Style,
+    /// Health of the unreachable servers, along with the errors.
+    pub(crate) down: Style,
+    /// Playback state of a replay.
+    pub(crate) info: Style,
+    /// Unavailable features and feedback of the actions.
+    pub(crate) warning: Style,
+    /// Commands unavailable in read-only mode.
+    pub(crate) disabled: Style,
+    pub(crate) footer_key: Style,
+    pub(crate) footer_label: Style,
+}
+
+impl Theme {
+    /// Themes which can be selected in the configuration file, `light` being also accepted for
+    /// `light-terminal`.
+    pub const NAMES: [&'static str; 4] =
+        ["default", "light-terminal", "solarized", "high-contrast"];
+
+    /// Looks the theme up by name, colors being left out altogether if `no_color` is set.
+    pub fn new(name: Option<&str>, no_color: bool) -> Result<Theme, ConfigError> {
+        let theme = match name.unwrap_or("default") {
+            "default" => Theme::default_theme(),
+            "light-terminal" | "light" => Theme::light(),
+            "solarized" => Theme::solarized(),
+            "high-contrast" => Theme::high_contrast(),
+            name => {
+                return Err(ConfigError::Message(format!(
+                    "unknown theme '{}', expected one of {}",
+                    name,
+                    Theme::NAMES.join(", ")
+                )))
+            }
+        };
+        Ok(if no_color { Theme::monochrome() } else { theme })
+    }
+
+    fn default_theme() -> Theme {
+        Theme {
+            title: Style::default()
+                .fg(Color::White)
+                .add_modifier(Modifier::BOLD),
+            header: Style::default().add_modifier(Modifier::BOLD),
+            selected: Style::default().add_modifier(Modifier::REVERSED),
+            alert: Style::default().fg(Color::Red).add_modifier(Modifier::BOLD),
+            tagged: Style::default()
+                .fg(Color::Yellow)
+                .add_modifier(Modifier::BOLD),
+            active: Style::default()
+                .fg(Color::Green)
+                .add_modifier(Modifier::BOLD),
+            up: Style::default().fg(Color::Green),
+            down: Style::default().fg(Color::Red),
+            info: Style::default().fg(Color::Cyan),
+            warning: Style::default().fg(Color::Yellow),
+            disabled: Style::default().fg(Color::DarkGray),
+            footer_key: Style::default().fg(Color::White),
+            footer_label: Style::default().fg(Color::Black).bg(Color::Cyan),
+        }
+    }
+
+    /// For terminals with a light background, on which white and yellow are unreadable.
+    fn light() -> Theme {
+        Theme {
+            title: Style::default()
+                .fg(Color::Blue)
+                .add_modifier(Modifier::BOLD),
+            info: Style::default().fg(Color::Blue),
+            warning: Style::default().fg(Color::Magenta),
+            tagged: Style::default()
+                .fg(Color::Magenta)
+                .add_modifier(Modifier::BOLD),
+            disabled: Style::default().fg(Color::Gray),
+            footer_key: Style::default().fg(Color::Black),
+            footer_label: Style::default().fg(Color::White).bg(Color::Blue),
+            ..Theme::default_theme()
+        }
+    }
+
+    /// Accent colors of the Solarized palette, readable on both its dark and light backgrounds.
+    fn solarized() -> Theme {
+        const BASE03: Color = Color::Rgb(0x00, 0x2b, 0x36);
+        const BASE01: Color = Color::Rgb(0x58, 0x6e, 0x75);
+        const BASE1: Color = Color::Rgb(0x93, 0xa1, 0xa1);
+        const YELLOW: Color = Color::Rgb(0xb5, 0x89, 0x00);
+        const RED: Color = Color::Rgb(0xdc, 0x32, 0x2f);
+        const BLUE: Color = Color::Rgb(0x26, 0x8b, 0xd2);
+        const CYAN: Color = Color::Rgb(0x2a, 0xa1, 0x98);
+        const GREEN: Color = Color::Rgb(0x85, 0x99, 0x00);
+        Theme {
+            title: Style::default().fg(BLUE).add_modifier(Modifier::BOLD),
+            header: Style::default().fg(BASE1).add_modifier(Modifier::BOLD),
+            selected: Style::default().add_modifier(Modifier::REVERSED),
+            alert: Style::default().fg(RED).add_modifier(Modifier::BOLD),
+            tagged: Style::default().fg(YELLOW).add_modifier(Modifier::BOLD),
+            active: Style::default().fg(GREEN).add_modifier(Modifier::BOLD),
+            up: Style::default().fg(GREEN),
+            down: Style::default().fg(RED),
+            info: Style::default().fg(CYAN),
+            warning: Style::default().fg(YELLOW),
+            disabled: Style::default().fg(BASE01),
+            footer_key: Style::default().fg(BASE1),
+            footer_label: Style::default().fg(BASE03).bg(CYAN),
+        }
+    }
+
+    /// Bright colors and backgrounds rather than hues alone to tell things apart.
+    fn high_contrast() -> Theme {
+        Theme {
+            title: Style::default()
+                .fg(Color::White)
+                .add_modifier(Modifier::BOLD),
+            header: Style::default()
+                .fg(Color::White)
+                .add_modifier(Modifier::BOLD | Modifier::UNDERLINED),
+            selected: Style::default()
+                .fg(Color::Black)
+                .bg(Color::Yellow)
+                .add_modifier(Modifier::BOLD),
+            alert: Style::default()
+                .fg(Color::White)
+                .bg(Color::Red)
+                .add_modifier(Modifier::BOLD),
+            tagged: Style::default()
+                .fg(Color::LightYellow)
+                .add_modifier(Modifier::BOLD | Modifier::UNDERLINED),
+            active: Style::default()
+                .fg(Color::LightGreen)
+                .add_modifier(Modifier::BOLD),
+            up: Style::default().fg(Color::LightGreen),
+            down: Style::default()
+                .fg(Color::LightRed)
+                .add_modifier(Modifier::BOLD),
+            info: Style::default().fg(Color::LightCyan),
+            warning: Style::default()
+                .fg(Color::LightYellow)
+                .add_modifier(Modifier::BOLD),
+            disabled: Style::default().fg(Color::Gray),
+            footer_key: Style::default()
+                .fg(Color::White)
+                .add_modifier(Modifier::BOLD),
+            footer_label: Style::default().fg(Color::Black).bg(Color::White),
+        }
+    }
+
+    /// Text attributes only, for `NO_COLOR` and `--no-color`.
+    fn monochrome() -> Theme {
+        Theme {
+            title: Style::default().add_modifier(Modifier::BOLD),
+            header: Style::default().add_modifier(Modifier::BOLD),
+            selected: Style::default().add_modifier(Modifier::REVERSED),
+            alert: Style::default().add_modifier(Modifier::BOLD | Modifier::UNDERLINED),
+            tagged: Style::default().add_modifier(Modifier::ITALIC | Modifier::UNDERLINED),
+            active: Style::default().add_modifier(Modifier::BOLD),
+            up: Style::default(),
+            down: Style::default().add_modifier(Modifier::BOLD),
+            info: Style::default(),
+            warning: Style::default().add_modifier(Modifier::BOLD),
+            disabled: Style::default().add_modifier(Modifier::DIM),
+            footer_key: Style::default().add_modifier(Modifier::BOLD),
+            footer_label: Style::default().add_modifier(Modifier::REVERSED),
+        }
+    }
+}
@@ -1,85 +1,299 @@
+use std::fmt;
 use std::io;
 use std::iter;
-use std::mem;
+use std::path::PathBuf;
+use std::sync::Arc;
 
+use chrono::{DateTime, Duration, Utc};
 use termion::event::Key;
 use termion::raw::IntoRawMode;
 use tui::backend::Backend;
 use tui::layout::Rect;
-use tui::style::Color;
-use tui::text::Span;
+use tui::text::{Span, Spans};
 use tui::{
     backend::TermionBackend,
-    layout::{Constraint, Layout},
-    style::{Modifier, Style},
-    widgets::{Block, Borders, Cell, Row, Table, TableState},
+    layout::{Constraint, Direction, Layout},
+    style::Style,
+    widgets::{Block, Borders, Cell, Clear, Paragraph, Row, Table, TableState, Wrap},
     Frame, Terminal,
 };
 
-use crate::db::{get_activities, get_system_info, PGStatActivity, PGSystemInfo};
-use crate::event::Event;
-use crate::{CliResult, Context};
-
-const TITLE_STYLE: Style = Style {
-    fg: Some(Color::White),
-    bg: None,
-    add_modifier: Modifier::BOLD,
-    sub_modifier: Modifier::empty(),
-};
-const SELECTED_STYLE: Style = Style {
-    fg: None,
-    bg: None,
-    add_modifier: Modifier::REVERSED,
-    sub_modifier: Modifier::empty(),
+use crate::alert::{Alert, Alerts};
+use crate::audit::{Action, AuditLog, Outcome};
+use crate::clipboard;
+use crate::columns::{ColumnLayout, Layouts};
+use crate::db::{PGStatActivity, PGSystemInfo};
+use crate::error::CliError;
+use crate::event::{self, Event, Events};
+use crate::explain::{is_hidden, is_truncated, ExplainMode, DEFAULT_QUERY_SIZE};
+use crate::format::{
+    format_duration, format_optional_duration, system_info_cells, ACTIVITY_COLUMNS, TABLE_COLUMNS,
 };
+use crate::group::{group_activities, Group, GroupKey};
+use crate::history::{History, Summary};
+use crate::keymap::{key_name, Command, Keymap};
+use crate::recording::Replay;
+use crate::server::ServerStatus;
+use crate::source::Source;
+use crate::theme::Theme;
+use crate::wait::{count_by, is_active, wait_label};
+use crate::CliResult;
 
-impl<'a> From<PGStatActivity> for Row<'a> {
-    fn from(activity: PGStatActivity) -> Row<'a> {
-        let mut state_cell_style = Style::default();
-        if activity.state == "active" {
-            state_cell_style = state_cell_style
-                .fg(Color::Green)
-                .add_modifier(Modifier::BOLD);
-        }
-
-        // todo: only show part of the query that fits
-        // let fmt_query: String = activity.query.chars().take(50).collect();
-
-        let cells = vec![
-            Cell::from(activity.datname),
-            Cell::from(activity.pid.to_string()),
-            Cell::from(activity.usename),
-            Cell::from(activity.client_addr),
-            Cell::from(activity.client_port.to_string()),
-            // Cell::from(activity.xact_start.unwrap_or(String::from(""))),
-            Cell::from(activity.backend_duration),
-            Cell::from(activity.query_duration),
-            Cell::from(activity.state).style(state_cell_style),
-            Cell::from(activity.query),
-        ];
+/// Number of snapshots skipped at once when replaying a recording.
+const REPLAY_SEEK_STEP: isize = 10;
+/// Alerts listed above the system information, the banner being cut past them.
+const MAX_ALERT_LINES: usize = 5;
+/// Lines of a plan scrolled past at once by PageUp/PageDown.
+const PLAN_PAGE: u16 = 10;
+/// Height of the wait events panel, borders included.
+const WAIT_PANEL_HEIGHT: u16 = 10;
+/// Samples of the session history the wait classes are shown over.
+const WAIT_SAMPLES: usize = 60;
+/// Characters of the last column scrolled past at once.
+const TEXT_SCROLL_STEP: usize = 20;
 
-        let height = 1u16;
-        Row::new(cells).height(height)
+/// Renders the visible columns of the activity past the scrolled ones, the state being
+/// highlighted when active.
+fn activity_row<'a>(
+    activity: &PGStatActivity,
+    layout: &ColumnLayout,
+    column_offset: usize,
+    text_offset: usize,
+    theme: &Theme,
+) -> Row<'a> {
+    let mut state_cell_style = Style::default();
+    if activity.state.as_deref() == Some("active") {
+        state_cell_style = theme.active;
     }
+
+    // todo: only show part of the query that fits
+    let nb_of_columns = layout.visible().count();
+    let cells = layout
+        .visible()
+        .zip(layout.cells(&ACTIVITY_COLUMNS, activity))
+        .enumerate()
+        .skip(column_offset)
+        .map(|(i, (entry, mut cell))| {
+            if i + 1 == nb_of_columns {
+                cell = cell.chars().skip(text_offset).collect();
+            }
+            if entry.name == "state" {
+                Cell::from(cell).style(state_cell_style)
+            } else {
+                Cell::from(cell)
+            }
+        })
+        .collect::<Vec<_>>();
+
+    let height = 1u16;
+    Row::new(cells).height(height)
+}
+
+/// Header of the visible columns past the scrolled ones.
+fn header_row<'a>(layout: &ColumnLayout, column_offset: usize, theme: &Theme) -> Row<'a> {
+    let header_cells = layout
+        .visible()
+        .skip(column_offset)
+        .map(|entry| Cell::from(entry.name).style(theme.header))
+        .collect::<Vec<_>>();
+    Row::new(header_cells).height(1)
+}
+
+/// Widths of the visible columns past the scrolled ones, the last one taking the remaining
+/// space.
+fn column_widths(layout: &ColumnLayout, column_offset: usize) -> Vec<Constraint> {
+    let nb_of_columns = layout.visible().count();
+    layout
+        .visible()
+        .enumerate()
+        .skip(column_offset)
+        .map(|(i, entry)| {
+            if i + 1 == nb_of_columns {
+                Constraint::Min(entry.width)
+            } else {
+                Constraint::Length(entry.width)
+            }
+        })
+        .collect()
 }
 
 impl<'a> From<&PGSystemInfo> for Row<'a> {
     fn from(system_info: &PGSystemInfo) -> Row<'a> {
-        let cells = vec![
-            // todo spans with different styles
-            Cell::from(format!("version: {}", system_info.version)),
-            Cell::from(format!("uptime: {}", system_info.uptime)),
-            Cell::from(format!("active connections: {}", system_info.nb_of_conn)),
-        ];
+        // todo spans with different styles
+        let cells = system_info_cells(system_info).into_iter().map(Cell::from);
 
         let height = 1u16;
         Row::new(cells).height(height)
     }
 }
 
+/// Identifies a backend across refreshes by pid and start, the start telling it apart from a
+/// later backend reusing its pid.
+type BackendKey = (i32, Option<DateTime<Utc>>);
+
+fn backend_key(activity: &PGStatActivity) -> BackendKey {
+    (activity.pid, activity.backend_start)
+}
+
+/// Whether a column of the activity, or its application, contains the text, ignoring case.
+fn matches_filter(activity: &PGStatActivity, filter: &str) -> bool {
+    let filter = filter.to_lowercase();
+    ACTIVITY_COLUMNS
+        .iter()
+        .map(|column| (column.cell)(activity))
+        .chain(activity.application_name.clone())
+        .any(|text| text.to_lowercase().contains(&filter))
+}
+
+/// Text of a backend copied to the clipboard.
+struct Copied {
+    what: &'static str,
+    text: String,
+    /// Whether the text is cut short, as queries longer than `track_activity_query_size` are.
+    truncated: bool,
+}
+
+/// Keys answering the prompts, listed in the help screen. They are not part of the keymap as
+/// they answer a question rather than run a command, and so cannot be rebound.
+const FIXED_KEYS: [(&str, &str); 4] = [
+    ("y", "confirm a cancellation, a termination or an analysis"),
+    ("q", "copy the query, at the copy prompt"),
+    ("p", "copy the pid, at the copy prompt"),
+    ("k", "copy the termination statement, at the copy prompt"),
+];
+
+/// Text of the selected backend copied to the clipboard, by key of the copy prompt. Hidden
+/// queries are refused, truncated ones being told apart with the `track_activity_query_size` of
+/// the server, or its default when unknown.
+fn copied_text(
+    key: Key,
+    activity: &PGStatActivity,
+    query_size: Option<usize>,
+) -> Option<Result<Copied, String>> {
+    let (what, text) = match key {
+        Key::Char('q') => {
+            let query = activity.query.clone().unwrap_or_default();
+            if is_hidden(&query) {
+                return Some(Err(format!(
+                    "query of {} not copied: hidden from pgtop",
+                    activity.pid
+                )));
+            }
+            ("query", query)
+        }
+        Key::Char('p') => ("pid", activity.pid.to_string()),
+        Key::Char('k') => (
+            "termination statement",
+            format!("SELECT pg_terminate_backend({});", activity.pid),
+        ),
+        _ => return None,
+    };
+    let truncated =
+        what == "query" && is_truncated(&text, query_size.unwrap_or(DEFAULT_QUERY_SIZE));
+    Some(Ok(Copied {
+        what,
+        text,
+        truncated,
+    }))
+}
+
+/// What the text typed at the prompt of the activities is about.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Prompt {
+    Pid,
+    Filter,
+}
+
+/// Index `step` rows away from the selected one among `len` rows, wrapping around the ends if
+/// `wrap` is set and stopping there otherwise. The first row is selected when none is.
+fn moved(selected: Option<usize>, len: usize, step: isize, wrap: bool) -> Option<usize> {
+    let len = len as isize;
+    if len == 0 {
+        return None;
+    }
+    let i = match selected {
+        Some(i) => i as isize + step,
+        None => 0,
+    };
+    let i = if wrap {
+        i.rem_euclid(len)
+    } else {
+        i.max(0).min(len - 1)
+    };
+    Some(i as usize)
+}
+
+/// Activities collapsed by an attribute.
+struct GroupsView {
+    key: GroupKey,
+    state: TableState,
+    groups: Vec<Group>,
+    /// Value of the selected group, which stays selected as the groups get reordered.
+    selected_value: Option<String>,
+    /// Value of the group whose members are listed instead of the groups.
+    expanded: Option<String>,
+}
+
+impl GroupsView {
+    fn new(key: GroupKey) -> GroupsView {
+        GroupsView {
+            key,
+            state: TableState::default(),
+            groups: vec![],
+            selected_value: None,
+            expanded: None,
+        }
+    }
+
+    /// Groups the activities, the selected group being looked up by value.
+    fn update(&mut self, activities: &[PGStatActivity]) {
+        self.groups = group_activities(activities, self.key);
+        let index = match &self.selected_value {
+            Some(value) => self
+                .groups
+                .iter()
+                .position(|group| group.value == *value)
+                .or_else(|| {
+                    let last = self.groups.len().checked_sub(1)?;
+                    self.state.selected().map(|i| i.min(last))
+                }),
+            None => None,
+        };
+        self.select(index);
+    }
+
+    fn select(&mut self, index: Option<usize>) {
+        self.state.select(index);
+        self.selected_value = index.map(|i| self.groups[i].value.clone());
+    }
+
+    fn selected(&self) -> Option<&Group> {
+        self.state.selected().and_then(|i| self.groups.get(i))
+    }
+
+    fn is_member(&self, activity: &PGStatActivity, group: &str) -> bool {
+        self.key.value(activity) == group
+    }
+}
+
 struct StatActivityView {
     state: TableState,
+    /// Activities of the server matching the filter.
     activities: Vec<PGStatActivity>,
+    filter: Option<String>,
+    /// Selected backend, which stays selected as the rows move between refreshes.
+    selected_backend: Option<BackendKey>,
+    /// Whether the selected backend ended, nothing being selected until the cursor moves.
+    vanished: bool,
+    /// Rows shown at once, as of the last draw.
+    page_size: usize,
+    /// Visible columns scrolled past.
+    column_offset: usize,
+    /// Characters of the last visible column scrolled past, once no other column is left.
+    text_offset: usize,
+    /// Backends tagged for a bulk action, among the activities.
+    tagged: Vec<BackendKey>,
+    grouping: Option<GroupsView>,
 }
 
 impl StatActivityView {
@@ -87,115 +301,1390 @@ impl StatActivityView {
         StatActivityView {
             state: TableState::default(),
             activities: vec![],
+            filter: None,
+            selected_backend: None,
+            vanished: false,
+            page_size: 1,
+            column_offset: 0,
+            text_offset: 0,
+            tagged: vec![],
+            grouping: None,
         }
     }
 
-    fn next(&mut self) {
-        let i = match self.state.selected() {
-            Some(i) => {
-                if i >= self.activities.len() - 1 {
-                    0
-                } else {
-                    i + 1
-                }
+    /// Groups listed instead of the activities, unless a group is expanded.
+    fn shown_groups(&mut self) -> Option<&mut GroupsView> {
+        self.grouping
+            .as_mut()
+            .filter(|grouping| grouping.expanded.is_none())
+    }
+
+    fn is_showing_groups(&self) -> bool {
+        matches!(&self.grouping, Some(grouping) if grouping.expanded.is_none())
+    }
+
+    /// Groups the activities by the next key, or stops grouping them after the last one.
+    fn cycle_grouping(&mut self, activities: Vec<PGStatActivity>) {
+        let key = GroupKey::cycle(self.grouping.as_ref().map(|grouping| grouping.key));
+        self.grouping = key.map(GroupsView::new);
+        self.select(None);
+        self.update(activities);
+    }
+
+    /// Lists the members of the selected group, or the groups again if expanded.
+    fn toggle_expanded(&mut self, activities: Vec<PGStatActivity>) {
+        let grouping = match &mut self.grouping {
+            Some(grouping) => grouping,
+            None => return,
+        };
+        grouping.expanded = match grouping.expanded {
+            Some(_) => None,
+            None => match grouping.selected() {
+                Some(group) => Some(group.value.clone()),
+                None => return,
+            },
+        };
+        self.select(None);
+        self.update(activities);
+    }
+
+    /// Starts over with the activities of another server, keeping the filter, the grouping key
+    /// and the scrolling.
+    fn reset(&mut self, activities: Vec<PGStatActivity>) {
+        self.state = TableState::default();
+        self.selected_backend = None;
+        self.vanished = false;
+        self.tagged.clear();
+        if let Some(grouping) = &mut self.grouping {
+            *grouping = GroupsView::new(grouping.key);
+        }
+        self.update(activities);
+    }
+
+    /// Replaces the activities, the ones not matching the filter being left out and untagged,
+    /// as well as the ones outside of the expanded group. The selected backend is looked up by
+    /// pid and start. Should it be gone, the cursor stays
+    /// at the same position without selecting the backend now shown there, which could
+    /// otherwise be cancelled or terminated by mistake.
+    fn update(&mut self, mut activities: Vec<PGStatActivity>) {
+        if let Some(filter) = &self.filter {
+            activities.retain(|activity| matches_filter(activity, filter));
+        }
+        if let Some(grouping) = &mut self.grouping {
+            grouping.update(&activities);
+            if let Some(group) = &grouping.expanded {
+                activities.retain(|activity| grouping.is_member(activity, group));
             }
-            None => 0,
+        }
+        self.activities = activities;
+        let activities = &self.activities;
+        self.tagged.retain(|tagged| {
+            activities
+                .iter()
+                .any(|activity| backend_key(activity) == *tagged)
+        });
+        let backend = match self.selected_backend {
+            Some(backend) => backend,
+            None => return,
         };
-        self.state.select(Some(i));
+        match self
+            .activities
+            .iter()
+            .position(|activity| backend_key(activity) == backend)
+        {
+            Some(i) => {
+                self.state.select(Some(i));
+                self.vanished = false;
+            }
+            None => {
+                let last = self.activities.len().checked_sub(1);
+                let cursor = self.state.selected().zip(last).map(|(i, last)| i.min(last));
+                self.state.select(cursor);
+                self.vanished = true;
+            }
+        }
+    }
+
+    fn select(&mut self, index: Option<usize>) {
+        self.state.select(index);
+        self.selected_backend = index.map(|i| backend_key(&self.activities[i]));
+        self.vanished = false;
+    }
+
+    /// The selected backend, unless it ended or the groups are shown.
+    fn selected(&self) -> Option<&PGStatActivity> {
+        if self.vanished || self.is_showing_groups() {
+            return None;
+        }
+        self.state.selected().and_then(|i| self.activities.get(i))
+    }
+
+    /// Selects the row, or group, `step` rows below the selected one, see `moved`.
+    fn move_by(&mut self, step: isize, wrap: bool) {
+        if let Some(grouping) = self.shown_groups() {
+            let index = moved(grouping.state.selected(), grouping.groups.len(), step, wrap);
+            grouping.select(index);
+            return;
+        }
+        if let Some(i) = moved(self.state.selected(), self.activities.len(), step, wrap) {
+            self.select(Some(i));
+        }
+    }
+
+    fn next(&mut self) {
+        self.move_by(1, true);
     }
 
     fn previous(&mut self) {
-        let i = match self.state.selected() {
+        self.move_by(-1, true);
+    }
+
+    fn page_down(&mut self) {
+        self.move_by(self.page_size as isize, false);
+    }
+
+    fn page_up(&mut self) {
+        self.move_by(-(self.page_size as isize), false);
+    }
+
+    fn first(&mut self) {
+        if let Some(grouping) = self.shown_groups() {
+            if !grouping.groups.is_empty() {
+                grouping.select(Some(0));
+            }
+        } else if !self.activities.is_empty() {
+            self.select(Some(0));
+        }
+    }
+
+    fn last(&mut self) {
+        if let Some(grouping) = self.shown_groups() {
+            grouping.select(grouping.groups.len().checked_sub(1));
+        } else {
+            self.select(self.activities.len().checked_sub(1));
+        }
+    }
+
+    /// Selects the backend of the pid, returning whether it is listed. The groups make way for
+    /// the activities, all of them being listed when not grouped.
+    fn select_pid(&mut self, pid: i32) -> bool {
+        if self.is_showing_groups() {
+            self.grouping = None;
+        }
+        match self
+            .activities
+            .iter()
+            .position(|activity| activity.pid == pid)
+        {
             Some(i) => {
-                if i == 0 {
-                    self.activities.len() - 1
-                } else {
-                    i - 1
-                }
+                self.select(Some(i));
+                true
             }
-            None => 0,
+            None => false,
+        }
+    }
+
+    /// Tags the selected backend, or untags it, and moves on to the next one.
+    fn toggle_tag(&mut self) {
+        let key = match self.selected() {
+            Some(activity) => backend_key(activity),
+            None => return,
         };
-        self.state.select(Some(i));
-    }
-
-    fn get_header_row<'a>() -> Row<'a> {
-        // style
-        let header_cols = [
-            "database",
-            "pid",
-            "user",
-            "client_addr",
-            "client_port",
-            // todo: xact only set for transaction
-            // "xact start",
-            "backend duration",
-            "query duration",
-            "state",
-            "query",
-        ];
-        let header_cells = header_cols
+        match self.tagged.iter().position(|tagged| *tagged == key) {
+            Some(i) => {
+                self.tagged.remove(i);
+            }
+            None => self.tagged.push(key),
+        }
+        self.move_by(1, false);
+    }
+
+    /// Tags every backend listed, or the members of the selected group when the groups are.
+    fn tag_all(&mut self) {
+        let group = match &self.grouping {
+            Some(grouping) if grouping.expanded.is_none() => match grouping.selected() {
+                Some(group) => Some((grouping, group.value.as_str())),
+                None => return,
+            },
+            _ => None,
+        };
+        for activity in &self.activities {
+            let key = backend_key(activity);
+            let member = match group {
+                Some((grouping, value)) => grouping.is_member(activity, value),
+                None => true,
+            };
+            if member && !self.tagged.contains(&key) {
+                self.tagged.push(key);
+            }
+        }
+    }
+
+    fn is_tagged(&self, activity: &PGStatActivity) -> bool {
+        self.tagged.contains(&backend_key(activity))
+    }
+
+    /// Backends an action applies to, the tagged ones if any or else the selected one.
+    fn targets(&self) -> Vec<PGStatActivity> {
+        if self.tagged.is_empty() {
+            return self.selected().cloned().into_iter().collect();
+        }
+        self.activities
+            .iter()
+            .filter(|activity| self.is_tagged(activity))
+            .cloned()
+            .collect()
+    }
+
+    /// Scrolls past the next visible column, or along the last one by `TEXT_SCROLL_STEP`
+    /// characters until its longest cell ends.
+    fn scroll_right(&mut self, layout: &ColumnLayout) {
+        if self.column_offset + 1 < layout.visible().count() {
+            self.column_offset += 1;
+            return;
+        }
+        let longest = self
+            .activities
             .iter()
-            .map(|h| Cell::from(*h).style(Style::default().add_modifier(Modifier::BOLD)));
-        Row::new(header_cells.clone()).height(1)
+            .filter_map(|activity| layout.cells(&ACTIVITY_COLUMNS, activity).pop())
+            .map(|cell| cell.chars().count())
+            .max()
+            .unwrap_or(0);
+        if self.text_offset + TEXT_SCROLL_STEP < longest {
+            self.text_offset += TEXT_SCROLL_STEP;
+        }
+    }
+
+    fn scroll_left(&mut self) {
+        if self.text_offset > 0 {
+            self.text_offset = self.text_offset.saturating_sub(TEXT_SCROLL_STEP);
+        } else {
+            self.column_offset = self.column_offset.saturating_sub(1);
+        }
     }
 }
 
-fn draw_system_info<B>(frame: &mut Frame<B>, system_info: &PGSystemInfo, layout_chunk: Rect)
-where
+/// Main panel of the interface, showing the details of the selected server.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum View {
+    Activities,
+    /// Tables of every database of the server, the most bloated first.
+    Tables,
+}
+
+impl fmt::Display for View {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            View::Activities => write!(f, "activities"),
+            View::Tables => write!(f, "tables"),
+        }
+    }
+}
+
+/// Servers being monitored, the activities of the selected one being detailed.
+struct ServersView {
+    statuses: Vec<ServerStatus>,
+    selected: usize,
+}
+
+impl ServersView {
+    fn new(statuses: Vec<ServerStatus>) -> ServersView {
+        ServersView {
+            statuses,
+            selected: 0,
+        }
+    }
+
+    fn update(&mut self, statuses: Vec<ServerStatus>) {
+        self.statuses = statuses;
+        self.selected = self.selected.min(self.statuses.len().saturating_sub(1));
+    }
+
+    fn selected(&self) -> Option<&ServerStatus> {
+        self.statuses.get(self.selected)
+    }
+
+    /// Activities of the selected server, if its snapshot could be taken.
+    fn activities(&self) -> Vec<PGStatActivity> {
+        match self.selected().map(|status| &status.snapshot) {
+            Some(Ok(snapshot)) => snapshot.activities.clone(),
+            _ => vec![],
+        }
+    }
+
+    fn next(&mut self) {
+        if !self.statuses.is_empty() {
+            self.selected = (self.selected + 1) % self.statuses.len();
+        }
+    }
+
+    fn previous(&mut self) {
+        if !self.statuses.is_empty() {
+            self.selected = (self.selected + self.statuses.len() - 1) % self.statuses.len();
+        }
+    }
+}
+
+/// Health, role and main figures of the server.
+fn server_row<'a>(status: &ServerStatus, theme: &Theme) -> Row<'a> {
+    let cells = match &status.snapshot {
+        Ok(snapshot) => {
+            let system_info = &snapshot.system_info;
+            let role = if system_info.in_recovery {
+                "standby"
+            } else {
+                "primary"
+            };
+            vec![
+                Cell::from(status.name.clone()),
+                Cell::from("up").style(theme.up),
+                Cell::from(role),
+                Cell::from(system_info.nb_of_conn.to_string()),
+                Cell::from(format_optional_duration(system_info.replication_lag())),
+                Cell::from(format_duration(system_info.uptime())),
+            ]
+        }
+        Err(err) => vec![
+            Cell::from(status.name.clone()),
+            Cell::from(format!("down: {}", err)).style(theme.down),
+        ],
+    };
+    Row::new(cells).height(1)
+}
+
+fn draw_servers<B>(
+    frame: &mut Frame<B>,
+    servers_view: &ServersView,
+    theme: &Theme,
+    layout_chunk: Rect,
+) where
     B: Backend,
 {
-    let rows = iter::once(system_info).map(Row::from);
-    let system_info_table = Table::new(rows)
+    let header_cells = ["server", "status", "role", "connections", "lag", "uptime"]
+        .iter()
+        .map(|h| Cell::from(*h).style(theme.header));
+    let rows = servers_view.statuses.iter().enumerate().map(|(i, status)| {
+        let row = server_row(status, theme);
+        if i == servers_view.selected {
+            row.style(theme.selected)
+        } else {
+            row
+        }
+    });
+    let servers_table = Table::new(rows)
+        .header(Row::new(header_cells).height(1))
         .widths(&[
-            Constraint::Percentage(60),
-            Constraint::Percentage(20),
-            Constraint::Percentage(20),
+            Constraint::Min(20),
+            Constraint::Min(10),
+            Constraint::Length(8),
+            Constraint::Length(11),
+            Constraint::Length(16),
+            Constraint::Length(16),
         ])
         .block(
             Block::default()
                 .borders(Borders::ALL)
-                .title(Span::styled(String::from("System"), TITLE_STYLE)),
+                .title(Span::styled(String::from("Servers"), theme.title)),
         );
-    frame.render_widget(system_info_table, layout_chunk);
+    frame.render_widget(servers_table, layout_chunk);
 }
 
+/// Describes the playback state, along with the keys controlling it.
+fn replay_status(replay: &Replay) -> String {
+    let timestamp = replay
+        .current()
+        .first()
+        .map(|snapshot| snapshot.timestamp.format("%F %T %Z").to_string())
+        .unwrap_or_default();
+    format!(
+        " replay {}/{} at {} ({}) ",
+        replay.position(),
+        replay.len(),
+        timestamp,
+        if replay.is_playing() {
+            "playing"
+        } else {
+            "paused"
+        },
+    )
+}
+
+/// Alerts firing on any of the servers, at most `layout_chunk` allows.
+fn draw_alerts<B>(frame: &mut Frame<B>, alerts: &[Alert], theme: &Theme, layout_chunk: Rect)
+where
+    B: Backend,
+{
+    let lines = alerts
+        .iter()
+        .map(|alert| Spans::from(Span::styled(alert.to_string(), theme.alert)))
+        .collect::<Vec<_>>();
+    let block = Block::default()
+        .borders(Borders::ALL)
+        .border_style(theme.alert)
+        .title(Span::styled(
+            format!("Alerts ({})", alerts.len()),
+            theme.alert,
+        ));
+    frame.render_widget(Paragraph::new(lines).block(block), layout_chunk);
+}
+
+fn draw_system_info<B>(
+    frame: &mut Frame<B>,
+    servers_view: &ServersView,
+    source: &Source,
+    theme: &Theme,
+    layout_chunk: Rect,
+) where
+    B: Backend,
+{
+    let mut title = vec![Span::styled(String::from("System"), theme.title)];
+    let status = servers_view.selected();
+    if let Some(status) = status.filter(|status| !status.name.is_empty()) {
+        title.push(Span::styled(format!(" {}", status.name), theme.title));
+    }
+    if let Source::Replay(replay) = source {
+        title.push(Span::styled(replay_status(replay), theme.info));
+    }
+    let unavailable = source
+        .capabilities(servers_view.selected)
+        .map(|capabilities| (capabilities.version, capabilities.unavailable()));
+    if let Some((version, unavailable)) = unavailable.filter(|(_, u)| !u.is_empty()) {
+        let features = unavailable
+            .iter()
+            .map(|feature| feature.to_string())
+            .collect::<Vec<_>>()
+            .join(", ");
+        title.push(Span::styled(
+            format!(" (unavailable on {}: {})", version, features),
+            theme.warning,
+        ));
+    }
+    let block = Block::default()
+        .borders(Borders::ALL)
+        .title(Spans::from(title));
+    match status.map(|status| &status.snapshot) {
+        Some(Ok(snapshot)) => {
+            let rows = iter::once(&snapshot.system_info).map(Row::from);
+            let system_info_table = Table::new(rows)
+                .widths(&[
+                    Constraint::Percentage(60),
+                    Constraint::Percentage(20),
+                    Constraint::Percentage(20),
+                ])
+                .block(block);
+            frame.render_widget(system_info_table, layout_chunk);
+        }
+        Some(Err(err)) => {
+            let error = Paragraph::new(Span::styled(format!("unreachable: {}", err), theme.down))
+                .block(block);
+            frame.render_widget(error, layout_chunk);
+        }
+        None => frame.render_widget(block, layout_chunk),
+    }
+}
 fn draw_activities<B>(
     frame: &mut Frame<B>,
-    activities: Vec<PGStatActivity>,
-    state: &mut TableState,
+    stat_activity_view: &mut StatActivityView,
+    layout: &ColumnLayout,
+    alerted_pids: &[i32],
+    message: Option<&str>,
+    theme: &Theme,
     layout_chunk: Rect,
 ) where
     B: Backend,
 {
-    let header = StatActivityView::get_header_row();
-    let rows = activities.into_iter().map(Row::from);
+    // columns may have been hidden since the view was scrolled
+    let nb_of_columns = layout.visible().count();
+    let column_offset = stat_activity_view
+        .column_offset
+        .min(nb_of_columns.saturating_sub(1));
+    stat_activity_view.column_offset = column_offset;
+    if column_offset + 1 < nb_of_columns {
+        stat_activity_view.text_offset = 0;
+    }
+    let text_offset = stat_activity_view.text_offset;
+    // borders and header
+    stat_activity_view.page_size = layout_chunk.height.saturating_sub(3).max(1) as usize;
+
+    let name = match &stat_activity_view.grouping {
+        Some(grouping) => match &grouping.expanded {
+            Some(group) => format!("Activities of {} {}", grouping.key.name(), group),
+            None => format!("Activities by {}", grouping.key.name()),
+        },
+        None => String::from("Activities"),
+    };
+    let mut title = vec![Span::styled(name, theme.title)];
+    if let Some(filter) = &stat_activity_view.filter {
+        title.push(Span::styled(format!(" filter: {} ", filter), theme.info));
+    }
+    if !stat_activity_view.tagged.is_empty() {
+        title.push(Span::styled(
+            format!(" {} tagged ", stat_activity_view.tagged.len()),
+            theme.tagged,
+        ));
+    }
+    if let Some(grouping) = stat_activity_view.shown_groups() {
+        if let Some(message) = message {
+            title.push(Span::styled(format!(" {} ", message), theme.warning));
+        }
+        draw_groups(frame, grouping, title, theme, layout_chunk);
+        return;
+    }
+
+    let header = header_row(layout, column_offset, theme);
+    let rows = stat_activity_view.activities.iter().map(|activity| {
+        let row = activity_row(activity, layout, column_offset, text_offset, theme);
+        if stat_activity_view.is_tagged(activity) {
+            row.style(theme.tagged)
+        } else if alerted_pids.contains(&activity.pid) {
+            row.style(theme.alert)
+        } else {
+            row
+        }
+    });
+    let mut highlight_style = theme.selected;
+    if let (true, Some((pid, _))) = (
+        stat_activity_view.vanished,
+        stat_activity_view.selected_backend,
+    ) {
+        // the row under the cursor is not the selected backend anymore
+        highlight_style = Style::default();
+        title.push(Span::styled(
+            format!(" backend {} ended ", pid),
+            theme.warning,
+        ));
+    }
+    if let Some(message) = message {
+        title.push(Span::styled(format!(" {} ", message), theme.warning));
+    }
+    let widths = column_widths(layout, column_offset);
     let stat_activity_table = Table::new(rows)
+        .header(header)
+        .widths(&widths)
+        .block(
+            Block::default()
+                .borders(Borders::ALL)
+                .title(Spans::from(title)),
+        )
+        .highlight_style(highlight_style);
+    frame.render_stateful_widget(
+        stat_activity_table,
+        layout_chunk,
+        &mut stat_activity_view.state,
+    );
+}
+
+fn draw_tables<B>(
+    frame: &mut Frame<B>,
+    servers_view: &ServersView,
+    layout: &ColumnLayout,
+    theme: &Theme,
+    layout_chunk: Rect,
+) where
+    B: Backend,
+{
+    let snapshot = match servers_view.selected().map(|status| &status.snapshot) {
+        Some(Ok(snapshot)) => Some(snapshot),
+        _ => None,
+    };
+    let mut title = vec![Span::styled(String::from("Tables"), theme.title)];
+    if let Some(snapshot) = snapshot.filter(|snapshot| !snapshot.table_errors.is_empty()) {
+        title.push(Span::styled(
+            format!(" ({})", snapshot.table_errors.join("; ")),
+            theme.warning,
+        ));
+    }
+    let block = Block::default()
+        .borders(Borders::ALL)
+        .title(Spans::from(title));
+    let tables = snapshot.and_then(|snapshot| snapshot.tables.as_ref());
+    let tables = match tables {
+        Some(tables) => tables,
+        None => {
+            let placeholder = Paragraph::new("table statistics are not available").block(block);
+            frame.render_widget(placeholder, layout_chunk);
+            return;
+        }
+    };
+    let mut tables = tables.iter().collect::<Vec<_>>();
+    tables.sort_by_key(|table| std::cmp::Reverse(table.n_dead_tup));
+    let rows = tables.into_iter().map(|table| {
+        Row::new(
+            layout
+                .cells(&TABLE_COLUMNS, table)
+                .into_iter()
+                .map(Cell::from)
+                .collect::<Vec<_>>(),
+        )
+        .height(1)
+    });
+    let widths = column_widths(layout, 0);
+    let tables_table = Table::new(rows)
+        .header(header_row(layout, 0, theme))
+        .widths(&widths)
+        .block(block);
+    frame.render_widget(tables_table, layout_chunk);
+}
+
+/// Whether the command can be used, actions being disabled in read-only mode.
+fn is_enabled(command: Command, source: &Source) -> bool {
+    !(command.is_action() && source.read_only())
+}
+
+/// Whether the command is relevant, playback commands being only shown when replaying.
+fn is_shown(command: Command, source: &Source) -> bool {
+    !command.is_replay_only() || matches!(source, Source::Replay(_))
+}
+
+/// htop-style bar listing the main commands along with their first key.
+fn draw_footer<B>(
+    frame: &mut Frame<B>,
+    keymap: &Keymap,
+    source: &Source,
+    theme: &Theme,
+    layout_chunk: Rect,
+) where
+    B: Backend,
+{
+    let replaying = matches!(source, Source::Replay(_));
+    let mut spans = vec![];
+    for command in Command::ALL.iter().copied() {
+        let (label, key) = match (command.label(), keymap.keys(command, replaying).first()) {
+            (Some(label), Some(key)) if is_shown(command, source) => (label, *key),
+            _ => continue,
+        };
+        let (key_style, label_style) = if is_enabled(command, source) {
+            (theme.footer_key, theme.footer_label)
+        } else {
+            (theme.disabled, theme.disabled)
+        };
+        spans.push(Span::styled(key_name(key), key_style));
+        spans.push(Span::styled(format!("{:<8}", label), label_style));
+    }
+    frame.render_widget(Paragraph::new(Spans::from(spans)), layout_chunk);
+}
+
+/// Hint at the first keys of the commands and what they do, as shown in the titles of the
+/// popups, whose keys are those of the commands available outside of a replay.
+fn key_hint(keymap: &Keymap, commands: &[Command], what: &str) -> String {
+    let keys = commands
+        .iter()
+        .filter_map(|command| keymap.keys(*command, false).first().copied())
+        .map(key_name)
+        .collect::<Vec<_>>();
+    format!(" [{}] {}", keys.join("/"), what)
+}
+
+/// Area of the given size, at most the one of `area`, centered in `area`.
+fn centered(area: Rect, width: u16, height: u16) -> Rect {
+    let width = area.width.min(width);
+    let height = area.height.min(height);
+    Rect::new(
+        area.x + (area.width - width) / 2,
+        area.y + (area.height - height) / 2,
+        width,
+        height,
+    )
+}
+
+/// Activities collapsed by a key, along with the figures of each group.
+fn draw_groups<B>(
+    frame: &mut Frame<B>,
+    grouping: &mut GroupsView,
+    title: Vec<Span>,
+    theme: &Theme,
+    layout_chunk: Rect,
+) where
+    B: Backend,
+{
+    let header = Row::new(vec![
+        grouping.key.name(),
+        "backends",
+        "active",
+        "oldest query",
+        "oldest xact",
+    ])
+    .style(theme.header)
+    .height(1);
+    let rows = grouping.groups.iter().map(|group| {
+        let value = if group.value.is_empty() {
+            String::from("(none)")
+        } else {
+            group.value.clone()
+        };
+        Row::new(vec![
+            value,
+            group.count.to_string(),
+            group.active.to_string(),
+            format_optional_duration(group.oldest_query),
+            format_optional_duration(group.oldest_xact),
+        ])
+        .height(1)
+    });
+    let groups_table = Table::new(rows)
         .header(header)
         .widths(&[
-            Constraint::Min(10),
-            Constraint::Length(5),
-            Constraint::Min(10),
-            Constraint::Min(10),
-            Constraint::Length(11),
-            // Constraint::Min(30),
-            Constraint::Min(30),
-            Constraint::Min(30),
-            Constraint::Min(10),
-            Constraint::Min(50),
+            Constraint::Min(20),
+            Constraint::Length(9),
+            Constraint::Length(7),
+            Constraint::Length(16),
+            Constraint::Length(16),
         ])
         .block(
             Block::default()
                 .borders(Borders::ALL)
-                .title(Span::styled(String::from("Activities"), TITLE_STYLE)),
+                .title(Spans::from(title)),
+        )
+        .highlight_style(theme.selected);
+    frame.render_stateful_widget(groups_table, layout_chunk, &mut grouping.state);
+}
+
+/// Renders the counts as a line of block characters, `max` getting a full block.
+fn sparkline(counts: &[usize], max: usize) -> String {
+    const BARS: [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+    counts
+        .iter()
+        .map(|count| match count {
+            0 => ' ',
+            count => BARS[((count * BARS.len() - 1) / max.max(1)).min(BARS.len() - 1)],
+        })
+        .collect()
+}
+
+/// Wait events of the active backends of the selected server, as counted in the last snapshot,
+/// along with the wait classes of the last samples of the session history.
+fn draw_waits<B>(
+    frame: &mut Frame<B>,
+    activities: &[PGStatActivity],
+    classes: &[(Arc<str>, Vec<usize>)],
+    theme: &Theme,
+    layout_chunk: Rect,
+) where
+    B: Backend,
+{
+    let block = Block::default().borders(Borders::ALL).title(Span::styled(
+        "Wait events of the active backends",
+        theme.title,
+    ));
+    let inner = block.inner(layout_chunk);
+    frame.render_widget(block, layout_chunk);
+    let halves = Layout::default()
+        .direction(Direction::Horizontal)
+        .constraints(vec![Constraint::Percentage(40), Constraint::Percentage(60)])
+        .split(inner);
+
+    let active = activities.iter().filter(|activity| is_active(activity));
+    let rows = count_by(active.map(wait_label))
+        .into_iter()
+        .map(|(event, count)| Row::new(vec![event, count.to_string()]).height(1));
+    let events = Table::new(rows)
+        .header(
+            Row::new(vec!["now", "backends"])
+                .style(theme.header)
+                .height(1),
+        )
+        .widths(&[Constraint::Min(20), Constraint::Length(8)]);
+    frame.render_widget(events, halves[0]);
+
+    let max = classes
+        .iter()
+        .flat_map(|(_, counts)| counts.iter().copied())
+        .max()
+        .unwrap_or(0);
+    let rows = classes.iter().map(|(class, counts)| {
+        let average = counts.iter().sum::<usize>() as f64 / counts.len().max(1) as f64;
+        Row::new(vec![
+            class.to_string(),
+            counts.last().copied().unwrap_or(0).to_string(),
+            format!("{:.1}", average),
+            sparkline(counts, max),
+        ])
+        .height(1)
+    });
+    let history = Table::new(rows)
+        .header(
+            Row::new(vec![
+                String::from("class"),
+                String::from("now"),
+                String::from("avg"),
+                format!("last {} samples", WAIT_SAMPLES),
+            ])
+            .style(theme.header)
+            .height(1),
+        )
+        .widths(&[
+            Constraint::Length(10),
+            Constraint::Length(4),
+            Constraint::Length(5),
+            Constraint::Min(10),
+        ]);
+    frame.render_widget(history, halves[1]);
+}
+
+/// Time window of the active session history shown.
+struct HistoryView {
+    /// Position of the window length in `History::windows`.
+    window: usize,
+    /// How long before the last sample the window ends.
+    before: Duration,
+}
+
+impl HistoryView {
+    /// Moves the window by half its length, at most as far back as the oldest sample.
+    fn shift(&mut self, history: &History, server: &str, earlier: bool) {
+        let step = History::window_length(self.window) / 2;
+        self.before = if earlier {
+            (self.before + step).min(history.span(server))
+        } else {
+            (self.before - step).max(Duration::zero())
+        };
+    }
+}
+
+/// Counts of the samples by label, along with the average number of active backends they
+/// amount to over the window.
+fn ranked_table<'a>(
+    label: &'a str,
+    counts: &'a [(Arc<str>, usize)],
+    summary: &Summary,
+    theme: &Theme,
+) -> Table<'a> {
+    let rows = counts.iter().map(|(value, count)| {
+        Row::new(vec![
+            count.to_string(),
+            format!("{:.2}", summary.average(*count)),
+            value.split_whitespace().collect::<Vec<_>>().join(" "),
+        ])
+        .height(1)
+    });
+    Table::new(rows)
+        .header(
+            Row::new(vec!["samples", "avg active", label])
+                .style(theme.header)
+                .height(1),
+        )
+        .widths(&[
+            Constraint::Length(8),
+            Constraint::Length(10),
+            Constraint::Min(20),
+        ])
+}
+
+/// Popup summing up the samples of the active backends of the selected server over a time
+/// window: their number over time, then the top wait events, users and queries.
+fn draw_history<B>(
+    frame: &mut Frame<B>,
+    history: &History,
+    history_view: &HistoryView,
+    server: Option<&str>,
+    keymap: &Keymap,
+    theme: &Theme,
+) where
+    B: Backend,
+{
+    let window = history.windows()[history_view.window];
+    let close = key_hint(keymap, &[Command::Close], "close");
+    let summary = server.and_then(|server| {
+        history.summary(
+            server,
+            History::window_length(history_view.window),
+            history_view.before,
         )
-        .highlight_style(SELECTED_STYLE);
-    frame.render_stateful_widget(stat_activity_table, layout_chunk, state);
+    });
+    let title = match &summary {
+        Some(summary) => format!(
+            "Active session history, {} to {}, {} samples{}{}{}",
+            summary.from.format("%F %T"),
+            summary.to.format("%T %Z"),
+            summary.active.len(),
+            key_hint(
+                keymap,
+                &[Command::NarrowWindow, Command::WidenWindow],
+                "window"
+            ),
+            key_hint(keymap, &[Command::ScrollLeft, Command::ScrollRight], "move"),
+            close,
+        ),
+        None => format!("Active session history{}", close),
+    };
+    let size = frame.size();
+    let popup = centered(
+        size,
+        size.width.saturating_sub(4),
+        size.height.saturating_sub(4),
+    );
+    let block = Block::default()
+        .borders(Borders::ALL)
+        .title(Span::styled(title, theme.title));
+    let inner = block.inner(popup);
+    frame.render_widget(Clear, popup);
+    frame.render_widget(block, popup);
+    let summary = match summary {
+        Some(summary) if !summary.active.is_empty() => summary,
+        _ => {
+            let empty = format!(
+                "No backend sampled in this {} window, samples are taken every {:?}",
+                window, history.sample_interval
+            );
+            frame.render_widget(Paragraph::new(empty), inner);
+            return;
+        }
+    };
+    let chunks = Layout::default()
+        .constraints(vec![
+            Constraint::Length(3),
+            Constraint::Percentage(40),
+            Constraint::Min(5),
+        ])
+        .split(inner);
+
+    // the busiest sample of each column, so that a short spike still shows
+    let width = usize::from(chunks[0].width).max(1);
+    let nb_of_samples = summary.active.len();
+    let columns = (0..width.min(nb_of_samples))
+        .map(|column| {
+            let start = column * nb_of_samples / width.min(nb_of_samples);
+            let end = (column + 1) * nb_of_samples / width.min(nb_of_samples);
+            summary.active[start..end]
+                .iter()
+                .copied()
+                .max()
+                .unwrap_or(0)
+        })
+        .collect::<Vec<_>>();
+    let max = summary.active.iter().copied().max().unwrap_or(0);
+    let total = summary.active.iter().sum::<usize>();
+    let timeline = vec![
+        Spans::from(Span::styled(
+            format!(
+                "Active backends over the last {} window: {:.2} on average, {} at most",
+                window,
+                summary.average(total),
+                max
+            ),
+            theme.header,
+        )),
+        Spans::from(sparkline(&columns, max)),
+    ];
+    frame.render_widget(Paragraph::new(timeline), chunks[0]);
+
+    let halves = Layout::default()
+        .direction(Direction::Horizontal)
+        .constraints(vec![Constraint::Percentage(50), Constraint::Percentage(50)])
+        .split(chunks[1]);
+    frame.render_widget(
+        ranked_table("wait event", &summary.wait_events, &summary, theme),
+        halves[0],
+    );
+    frame.render_widget(
+        ranked_table("user", &summary.users, &summary, theme),
+        halves[1],
+    );
+    frame.render_widget(
+        ranked_table("query", &summary.queries, &summary, theme),
+        chunks[2],
+    );
 }
 
-pub async fn start_ui(ctx: Context) -> CliResult<()> {
+/// Popup showing, hiding, reordering and resizing the columns of a view.
+fn draw_setup<B>(
+    frame: &mut Frame<B>,
+    view: View,
+    layout: &ColumnLayout,
+    cursor: usize,
+    keymap: &Keymap,
+    theme: &Theme,
+) where
+    B: Backend,
+{
+    let rows = layout
+        .entries
+        .iter()
+        .enumerate()
+        .map(|(i, entry)| {
+            let row = Row::new(vec![
+                Cell::from(if entry.visible { "[x]" } else { "[ ]" }),
+                Cell::from(entry.name),
+                Cell::from(entry.width.to_string()),
+            ])
+            .height(1);
+            if i == cursor {
+                row.style(theme.selected)
+            } else {
+                row
+            }
+        })
+        .collect::<Vec<_>>();
+    let popup = centered(frame.size(), 80, rows.len() as u16 + 3);
+    let setup = Table::new(rows)
+        .header(
+            Row::new(vec!["", "column", "width"])
+                .style(theme.header)
+                .height(1),
+        )
+        .widths(&[
+            Constraint::Length(3),
+            Constraint::Min(20),
+            Constraint::Length(5),
+        ])
+        .block(Block::default().borders(Borders::ALL).title(Span::styled(
+            format!(
+                "Columns of the {}{}{}{}{}{}",
+                view,
+                key_hint(keymap, &[Command::Tag], "show/hide"),
+                key_hint(
+                    keymap,
+                    &[Command::ScrollLeft, Command::ScrollRight],
+                    "width"
+                ),
+                key_hint(
+                    keymap,
+                    &[Command::MoveColumnLeft, Command::MoveColumnRight],
+                    "move"
+                ),
+                key_hint(keymap, &[Command::Expand], "save"),
+                key_hint(keymap, &[Command::Close], "cancel"),
+            ),
+            theme.title,
+        )));
+    frame.render_widget(Clear, popup);
+    frame.render_widget(setup, popup);
+}
+
+/// Popup listing every key binding, drawn over the rest of the interface.
+fn draw_help<B>(frame: &mut Frame<B>, keymap: &Keymap, source: &Source, theme: &Theme)
+where
+    B: Backend,
+{
+    let replaying = matches!(source, Source::Replay(_));
+    let rows = Command::ALL
+        .iter()
+        .copied()
+        .filter(|command| is_shown(*command, source))
+        .map(|command| {
+            let keys = keymap
+                .keys(command, replaying)
+                .into_iter()
+                .map(key_name)
+                .collect::<Vec<_>>()
+                .join(", ");
+            let mut description = String::from(command.description());
+            let row = if is_enabled(command, source) {
+                Row::new(vec![Cell::from(keys), Cell::from(description)])
+            } else {
+                description.push_str(" (read-only)");
+                Row::new(vec![Cell::from(keys), Cell::from(description)]).style(theme.disabled)
+            };
+            row.height(1)
+        })
+        .chain(iter::once(
+            Row::new(vec![Cell::from("Fixed keys")]).style(theme.header),
+        ))
+        .chain(FIXED_KEYS.iter().map(|(key, description)| {
+            Row::new(vec![Cell::from(*key), Cell::from(*description)]).height(1)
+        }))
+        .collect::<Vec<_>>();
+    let popup = centered(frame.size(), 70, rows.len() as u16 + 2);
+    let help = Table::new(rows)
+        .widths(&[Constraint::Length(20), Constraint::Min(30)])
+        .block(Block::default().borders(Borders::ALL).title(Span::styled(
+            String::from("Help, any key to close"),
+            theme.title,
+        )));
+    frame.render_widget(Clear, popup);
+    frame.render_widget(help, popup);
+}
+
+/// Query of a backend along with its plan, shown over the activities.
+struct PlanView {
+    activity: PGStatActivity,
+    mode: ExplainMode,
+    plan: Vec<String>,
+    /// Lines scrolled past.
+    scroll: u16,
+    /// Whether running the query with EXPLAIN ANALYZE awaits confirmation.
+    confirm_analyze: bool,
+    /// Why the last attempt at analyzing the query failed.
+    error: Option<String>,
+}
+
+/// Popup showing the query of a backend and its plan.
+fn draw_plan<B>(
+    frame: &mut Frame<B>,
+    plan_view: &mut PlanView,
+    read_only: bool,
+    keymap: &Keymap,
+    theme: &Theme,
+) where
+    B: Backend,
+{
+    let query = plan_view.activity.query.as_deref().unwrap_or_default();
+    let kind = match plan_view.mode {
+        ExplainMode::Plan => "Plan",
+        ExplainMode::GenericPlan => "Generic plan",
+        ExplainMode::Analyze => "Plan analyzed",
+    };
+    let lines = iter::once(Spans::from(Span::styled("Query", theme.header)))
+        .chain(query.lines().map(Spans::from))
+        .chain(iter::once(Spans::default()))
+        .chain(iter::once(Spans::from(Span::styled(kind, theme.header))))
+        .chain(plan_view.plan.iter().map(|line| Spans::from(line.as_str())))
+        .collect::<Vec<_>>();
+    let title = if plan_view.confirm_analyze {
+        Span::styled(
+            "Run the query with EXPLAIN ANALYZE, in a transaction rolled back afterwards? [y] confirm",
+            theme.warning,
+        )
+    } else if let Some(error) = &plan_view.error {
+        Span::styled(error.clone(), theme.warning)
+    } else {
+        let analyze = match plan_view.mode {
+            ExplainMode::Plan if !read_only => key_hint(keymap, &[Command::Analyze], "analyze"),
+            _ => String::new(),
+        };
+        Span::styled(
+            format!(
+                "Backend {}{}{}{}",
+                plan_view.activity.pid,
+                key_hint(keymap, &[Command::Up, Command::Down], "scroll"),
+                analyze,
+                key_hint(keymap, &[Command::Close], "close"),
+            ),
+            theme.title,
+        )
+    };
+    let size = frame.size();
+    let popup = centered(
+        size,
+        size.width.saturating_sub(4),
+        size.height.saturating_sub(4),
+    );
+    // lines wrapped at the width of the popup, words wrapped as a whole possibly taking more
+    let width = popup.width.saturating_sub(2).max(1) as usize;
+    let wrapped = lines
+        .iter()
+        .map(|line| line.width().div_ceil(width).max(1))
+        .sum::<usize>();
+    let last = wrapped.saturating_sub(popup.height.saturating_sub(2) as usize);
+    plan_view.scroll = plan_view.scroll.min(last.min(u16::MAX as usize) as u16);
+    let paragraph = Paragraph::new(lines)
+        .block(Block::default().borders(Borders::ALL).title(title))
+        .wrap(Wrap { trim: false })
+        .scroll((plan_view.scroll, 0));
+    frame.render_widget(Clear, popup);
+    frame.render_widget(paragraph, popup);
+}
+
+/// Outcome of a bulk action for each backend, shown over the activities.
+struct OutcomesView {
+    action: Action,
+    outcomes: Vec<(Outcome, String)>,
+    /// Lines scrolled past.
+    scroll: usize,
+    /// Lines of the list shown at once, as of the last draw.
+    height: usize,
+}
+
+impl OutcomesView {
+    /// Scrolls the list, returning whether the command is one that scrolls.
+    fn scroll(&mut self, command: Option<Command>) -> bool {
+        let last = self.outcomes.len().saturating_sub(self.height);
+        self.scroll = match command {
+            Some(Command::Down) => self.scroll + 1,
+            Some(Command::Up) => self.scroll.saturating_sub(1),
+            Some(Command::PageDown) => self.scroll + self.height,
+            Some(Command::PageUp) => self.scroll.saturating_sub(self.height),
+            Some(Command::First) => 0,
+            Some(Command::Last) => last,
+            _ => return false,
+        }
+        .min(last);
+        true
+    }
+}
+
+/// Popup counting the outcomes of a bulk action, then listing the outcome for each backend.
+fn draw_outcomes<B>(
+    frame: &mut Frame<B>,
+    outcomes_view: &mut OutcomesView,
+    keymap: &Keymap,
+    theme: &Theme,
+) where
+    B: Backend,
+{
+    let count = |kind: Outcome| {
+        outcomes_view
+            .outcomes
+            .iter()
+            .filter(|(outcome, _)| *outcome == kind)
+            .count()
+    };
+    let counts = format!(
+        "{} {}, {} gone, {} failed",
+        count(Outcome::Done),
+        outcomes_view.action.done(),
+        count(Outcome::Gone),
+        count(Outcome::Failed)
+    );
+    let popup = centered(
+        frame.size(),
+        80,
+        (outcomes_view.outcomes.len() as u16).saturating_add(4),
+    );
+    // borders, then the counts and a blank line
+    outcomes_view.height = popup.height.saturating_sub(4) as usize;
+    outcomes_view.scroll = outcomes_view.scroll.min(
+        outcomes_view
+            .outcomes
+            .len()
+            .saturating_sub(outcomes_view.height),
+    );
+    let mut lines = vec![
+        Spans::from(Span::styled(counts, theme.title)),
+        Spans::from(""),
+    ];
+    lines.extend(
+        outcomes_view
+            .outcomes
+            .iter()
+            .skip(outcomes_view.scroll)
+            .take(outcomes_view.height)
+            .map(|(_, outcome)| Spans::from(outcome.as_str())),
+    );
+    let summary =
+        Paragraph::new(lines).block(Block::default().borders(Borders::ALL).title(Span::styled(
+            format!(
+                "Outcome of {}{}, any other key to close",
+                outcomes_view.action,
+                key_hint(keymap, &[Command::Up, Command::Down], "scroll")
+            ),
+            theme.title,
+        )));
+    frame.render_widget(Clear, popup);
+    frame.render_widget(summary, popup);
+}
+
+/// Backends of the selected server some alert is about.
+fn alerted_pids(alerts: &[Alert], servers_view: &ServersView) -> Vec<i32> {
+    let server = match servers_view.selected() {
+        Some(status) => &status.name,
+        None => return vec![],
+    };
+    alerts
+        .iter()
+        .filter(|alert| &alert.server == server)
+        .flat_map(|alert| alert.pids.iter().copied())
+        .collect()
+}
+
+/// Audit log, opened on the first action.
+fn open_audit<'a>(
+    audit: &'a mut Option<AuditLog>,
+    audit_path: Option<&str>,
+) -> CliResult<&'a mut AuditLog> {
+    match audit {
+        Some(audit) => Ok(audit),
+        None => Ok(audit.insert(AuditLog::open(audit_path)?)),
+    }
+}
+
+/// Cancels or terminates the backends once confirmed, returning what happened to each of them.
+/// The audit log is opened on the first action, nothing being done if it cannot be.
+async fn confirm_action(
+    source: &mut Source,
+    server: usize,
+    action: Action,
+    activities: &[PGStatActivity],
+    audit: &mut Option<AuditLog>,
+    audit_path: Option<&str>,
+) -> Vec<(Outcome, String)> {
+    let audit = match open_audit(audit, audit_path) {
+        Ok(audit) => audit,
+        Err(err) => {
+            return vec![(
+                Outcome::Failed,
+                format!("audit log unavailable, nothing done: {}", err),
+            )]
+        }
+    };
+    let mut outcomes = vec![];
+    for activity in activities {
+        outcomes.push(match source.act(server, action, activity, audit).await {
+            Ok((outcome, description)) => (
+                outcome,
+                format!("{} {}: {}", action, activity.pid, description),
+            ),
+            Err(err) => (
+                Outcome::Failed,
+                format!("{} {}: {}", action, activity.pid, err),
+            ),
+        });
+    }
+    outcomes
+}
+
+/// Settings of the interactive interface.
+pub struct Options<'a> {
+    pub(crate) keymap: Keymap,
+    pub(crate) layouts: Layouts,
+    pub(crate) theme: Theme,
+    /// Active session history, sampled as long as the interface runs.
+    pub(crate) history: History,
+    pub(crate) audit_log: Option<&'a str>,
+    /// Configuration file the column layouts are saved to.
+    pub(crate) config_path: Option<PathBuf>,
+}
+
+pub async fn start_ui(
+    mut source: Source,
+    mut alerts: Alerts,
+    options: Options<'_>,
+) -> CliResult<()> {
+    let Options {
+        keymap,
+        mut layouts,
+        theme,
+        mut history,
+        audit_log: audit_path,
+        config_path,
+    } = options;
+    // recordings are not sampled, their snapshots are all the history there is
+    let replaying = matches!(source, Source::Replay(_));
+    let events = Events::with_config(event::Config {
+        tick_rate: source.refresh_rate(),
+        sample_rate: Some(history.sample_interval).filter(|_| !replaying),
+    });
+
     // data initial fetch (refreshed at each tick)
     let mut stat_activity_view = StatActivityView::new();
-    let mut system_info = get_system_info(&ctx.client).await?;
+    let mut view = View::Activities;
+    let mut servers_view = ServersView::new(source.fetch(false).await?);
+    stat_activity_view.update(servers_view.activities());
+    // hooks are not run again when replaying a recording
+    let hooks = !replaying;
+    let mut firing = alerts.update(&servers_view.statuses, hooks);
+    let mut show_waits = false;
+    if replaying {
+        history.record_statuses(&servers_view.statuses);
+    }
+    // time window of the session history, when shown
+    let mut history_view: Option<HistoryView> = None;
+    // action awaiting confirmation, and the feedback shown along the activities
+    let mut pending: Option<(Action, Vec<PGStatActivity>)> = None;
+    let mut message: Option<String> = None;
+    let mut audit: Option<AuditLog> = None;
+    // backend whose query, pid or termination statement is about to be copied
+    let mut copying: Option<PGStatActivity> = None;
+    // query and plan of a backend, when shown
+    let mut plan_view: Option<PlanView> = None;
+    // outcome of the last bulk action, until dismissed
+    let mut outcomes_view: Option<OutcomesView> = None;
+    let mut show_help = false;
+    // position of the column selected in the column chooser, when shown, along with the layout
+    // as it was when opened
+    let mut setup: Option<(usize, ColumnLayout)> = None;
+    // text typed so far when asked for a pid or a filter
+    let mut prompt: Option<(Prompt, String)> = None;
 
     // Terminal initialization
     let stdout = io::stdout().into_raw_mode()?;
@@ -205,43 +1694,534 @@ pub async fn start_ui(ctx: Context) -> CliResult<()> {
     terminal.hide_cursor()?;
     terminal.clear()?;
     loop {
-        terminal.draw(|mut frame| {
-            // UI layout, each rectangle is a section
-            let main_layout = Layout::default()
-                .constraints([Constraint::Length(3), Constraint::Min(20)].as_ref())
+        terminal.draw(|frame| {
+            // UI layout, each rectangle is a section, the servers being only listed when
+            // monitoring several of them and the alerts while some are firing
+            let nb_of_servers = servers_view.statuses.len();
+            let mut constraints = vec![
+                Constraint::Length(3),
+                Constraint::Min(20),
+                Constraint::Length(1),
+            ];
+            if !firing.is_empty() {
+                let height = firing.len().min(MAX_ALERT_LINES) as u16 + 2;
+                constraints.insert(0, Constraint::Length(height));
+            }
+            if nb_of_servers > 1 {
+                constraints.insert(0, Constraint::Length(nb_of_servers as u16 + 3));
+            }
+            let mut main_layout = Layout::default()
+                .constraints(constraints)
                 .margin(1)
                 .split(frame.size());
+            if nb_of_servers > 1 {
+                draw_servers(frame, &servers_view, &theme, main_layout.remove(0));
+            }
+            if !firing.is_empty() {
+                draw_alerts(frame, &firing, &theme, main_layout.remove(0));
+            }
 
-            draw_system_info(&mut frame, &system_info, main_layout[0]);
-            draw_activities(
-                &mut frame,
-                mem::take(&mut stat_activity_view.activities),
-                &mut stat_activity_view.state,
-                main_layout[1],
-            );
+            draw_system_info(frame, &servers_view, &source, &theme, main_layout[0]);
+            let prompt = match (&copying, &prompt) {
+                (Some(activity), _) => Some(format!(
+                    "copy {}: [q] query [p] pid [k] termination statement",
+                    activity.pid
+                )),
+                (None, Some((Prompt::Pid, text))) => {
+                    Some(format!("pid: {}_ [Enter] select [Esc] cancel", text))
+                }
+                (None, Some((Prompt::Filter, text))) => {
+                    Some(format!("filter: {}_ [Enter] apply [Esc] cancel", text))
+                }
+                (None, None) => None,
+            };
+            match view {
+                View::Activities => {
+                    let mut activities_chunk = main_layout[1];
+                    if show_waits {
+                        let chunks = Layout::default()
+                            .constraints(vec![
+                                Constraint::Min(8),
+                                Constraint::Length(WAIT_PANEL_HEIGHT),
+                            ])
+                            .split(activities_chunk);
+                        activities_chunk = chunks[0];
+                        let classes = match servers_view.selected() {
+                            Some(status) => history.classes(&status.name, WAIT_SAMPLES),
+                            None => vec![],
+                        };
+                        draw_waits(
+                            frame,
+                            &servers_view.activities(),
+                            &classes,
+                            &theme,
+                            chunks[1],
+                        );
+                    }
+                    draw_activities(
+                        frame,
+                        &mut stat_activity_view,
+                        &layouts.activities,
+                        &alerted_pids(&firing, &servers_view),
+                        prompt.as_deref().or(message.as_deref()),
+                        &theme,
+                        activities_chunk,
+                    )
+                }
+                View::Tables => draw_tables(
+                    frame,
+                    &servers_view,
+                    &layouts.tables,
+                    &theme,
+                    main_layout[1],
+                ),
+            }
+            draw_footer(frame, &keymap, &source, &theme, main_layout[2]);
+            if let Some(plan_view) = &mut plan_view {
+                draw_plan(frame, plan_view, source.read_only(), &keymap, &theme);
+            }
+            if let Some(history_view) = &history_view {
+                let server = servers_view.selected().map(|status| status.name.as_str());
+                draw_history(frame, &history, history_view, server, &keymap, &theme);
+            }
+            if let Some(outcomes_view) = &mut outcomes_view {
+                draw_outcomes(frame, outcomes_view, &keymap, &theme);
+            }
+            if show_help {
+                draw_help(frame, &keymap, &source, &theme);
+            }
+            if let Some((cursor, _)) = &setup {
+                let layout = match view {
+                    View::Activities => &layouts.activities,
+                    View::Tables => &layouts.tables,
+                };
+                draw_setup(frame, view, layout, *cursor, &keymap, &theme);
+            }
         })?;
 
-        match ctx.events.next()? {
-            Event::Input(key) => match key {
-                Key::Char('q') | Key::Ctrl('c') => {
+        let statuses = match events.next()? {
+            Event::Input(key) if pending.is_some() => {
+                let (action, targets) = pending.take().expect("checked above");
+                message = None;
+                if key == Key::Char('y') {
+                    let mut results = confirm_action(
+                        &mut source,
+                        servers_view.selected,
+                        action,
+                        &targets,
+                        &mut audit,
+                        audit_path,
+                    )
+                    .await;
+                    if targets.len() > 1 {
+                        stat_activity_view.tagged.clear();
+                        outcomes_view = Some(OutcomesView {
+                            action,
+                            outcomes: results,
+                            scroll: 0,
+                            height: 0,
+                        });
+                    } else {
+                        message = results.pop().map(|(_, outcome)| outcome);
+                    }
+                    Some(source.fetch(view == View::Tables).await?)
+                } else {
+                    None
+                }
+            }
+            Event::Input(key) if plan_view.is_some() => {
+                let shown = plan_view.as_mut().expect("checked above");
+                if shown.confirm_analyze {
+                    shown.confirm_analyze = false;
+                    if key == Key::Char('y') {
+                        let analyzed = match open_audit(&mut audit, audit_path) {
+                            Ok(audit) => {
+                                source
+                                    .analyze(servers_view.selected, &shown.activity, audit)
+                                    .await
+                            }
+                            Err(err) => Err(err),
+                        };
+                        match analyzed {
+                            Ok((mode, plan)) => {
+                                shown.mode = mode;
+                                shown.plan = plan;
+                                shown.scroll = 0;
+                                shown.error = None;
+                            }
+                            Err(err) => shown.error = Some(err.to_string()),
+                        }
+                    }
+                } else {
+                    match keymap.command(key, false) {
+                        Some(Command::Up) => shown.scroll = shown.scroll.saturating_sub(1),
+                        Some(Command::Down) => shown.scroll = shown.scroll.saturating_add(1),
+                        Some(Command::PageUp) => {
+                            shown.scroll = shown.scroll.saturating_sub(PLAN_PAGE)
+                        }
+                        Some(Command::PageDown) => {
+                            shown.scroll = shown.scroll.saturating_add(PLAN_PAGE)
+                        }
+                        Some(Command::First) => shown.scroll = 0,
+                        Some(Command::Last) => shown.scroll = u16::MAX,
+                        Some(Command::Analyze) if shown.mode == ExplainMode::Plan => {
+                            if source.read_only() {
+                                shown.error = Some(format!("analyze: {}", CliError::ReadOnly));
+                            } else {
+                                shown.confirm_analyze = true;
+                            }
+                        }
+                        Some(Command::Close) | Some(Command::Quit) | Some(Command::Explain) => {
+                            plan_view = None
+                        }
+                        _ => {}
+                    }
+                }
+                None
+            }
+            Event::Input(key) if history_view.is_some() => {
+                let shown = history_view.as_mut().expect("checked above");
+                let server = servers_view.selected().map(|status| status.name.clone());
+                match keymap.command(key, false) {
+                    Some(Command::NarrowWindow) => shown.window = shown.window.saturating_sub(1),
+                    Some(Command::WidenWindow) => {
+                        shown.window = (shown.window + 1).min(history.windows().len() - 1)
+                    }
+                    Some(command @ Command::ScrollLeft) | Some(command @ Command::ScrollRight) => {
+                        if let Some(server) = &server {
+                            shown.shift(&history, server, command == Command::ScrollLeft);
+                        }
+                    }
+                    Some(Command::Last) => shown.before = Duration::zero(),
+                    Some(Command::Close) | Some(Command::Quit) | Some(Command::History) => {
+                        history_view = None
+                    }
+                    _ => {}
+                }
+                None
+            }
+            Event::Input(key) if copying.is_some() => {
+                let activity = copying.take().expect("checked above");
+                let query_size = match key {
+                    Key::Char('q') => source.query_size(servers_view.selected).await,
+                    _ => None,
+                };
+                message = copied_text(key, &activity, query_size).map(|copied| match copied {
+                    Ok(copied) => match clipboard::copy(&copied.text) {
+                        Ok(()) if copied.truncated => format!(
+                            "{} of {} copied, truncated at track_activity_query_size",
+                            copied.what, activity.pid
+                        ),
+                        Ok(()) => format!("{} of {} copied", copied.what, activity.pid),
+                        Err(err) => {
+                            format!("{} of {} not copied: {}", copied.what, activity.pid, err)
+                        }
+                    },
+                    Err(reason) => reason,
+                });
+                None
+            }
+            Event::Input(key) if outcomes_view.is_some() => {
+                let shown = outcomes_view.as_mut().expect("checked above");
+                if !shown.scroll(keymap.command(key, false)) {
+                    outcomes_view = None;
+                }
+                None
+            }
+            Event::Input(key) if prompt.is_some() => {
+                let (kind, text) = prompt.as_mut().expect("checked above");
+                match key {
+                    Key::Char('\n') => {
+                        match kind {
+                            Prompt::Pid => {
+                                message = match text.parse() {
+                                    Ok(pid) if stat_activity_view.select_pid(pid) => None,
+                                    _ => Some(format!("no backend with pid {}", text)),
+                                };
+                            }
+                            Prompt::Filter => {
+                                stat_activity_view.filter =
+                                    Some(text.clone()).filter(|text| !text.is_empty());
+                                stat_activity_view.update(servers_view.activities());
+                            }
+                        }
+                        prompt = None;
+                    }
+                    Key::Char(c) if *kind == Prompt::Filter || c.is_ascii_digit() => text.push(c),
+                    Key::Backspace => {
+                        text.pop();
+                    }
+                    Key::Esc => prompt = None,
+                    _ => {}
+                }
+                None
+            }
+            Event::Input(_) if show_help => {
+                show_help = false;
+                None
+            }
+            Event::Input(key) if setup.is_some() => {
+                let (cursor, _) = setup.as_mut().expect("checked above");
+                let layout = match view {
+                    View::Activities => &mut layouts.activities,
+                    View::Tables => &mut layouts.tables,
+                };
+                let nb_of_columns = layout.entries.len();
+                match keymap.command(key, false) {
+                    Some(Command::Up) => *cursor = (*cursor + nb_of_columns - 1) % nb_of_columns,
+                    Some(Command::Down) => *cursor = (*cursor + 1) % nb_of_columns,
+                    Some(Command::Tag) => {
+                        let visible = layout.entries[*cursor].visible;
+                        // at least one column is kept so that the view is not left empty
+                        if !visible || layout.visible().count() > 1 {
+                            layout.entries[*cursor].visible = !visible;
+                        }
+                    }
+                    Some(Command::ScrollLeft) => {
+                        let entry = &mut layout.entries[*cursor];
+                        entry.width = entry.width.saturating_sub(1).max(1);
+                    }
+                    Some(Command::ScrollRight) => {
+                        let entry = &mut layout.entries[*cursor];
+                        entry.width = entry.width.saturating_add(1);
+                    }
+                    Some(Command::MoveColumnLeft) => *cursor = layout.shift(*cursor, true),
+                    Some(Command::MoveColumnRight) => *cursor = layout.shift(*cursor, false),
+                    Some(Command::Close) => {
+                        let (_, original) = setup.take().expect("checked above");
+                        *layout = original;
+                    }
+                    Some(Command::Expand) => {
+                        setup = None;
+                        message = Some(match &config_path {
+                            Some(path) => match layouts.save(path) {
+                                Ok(()) => format!("columns saved to {}", path.display()),
+                                Err(err) => format!("columns not saved: {}", err),
+                            },
+                            None => String::from(
+                                "columns not saved: no single configuration file to write to",
+                            ),
+                        });
+                    }
+                    _ => {}
+                }
+                None
+            }
+            Event::Input(key) => match (keymap.command(key, replaying), &mut source) {
+                (Some(Command::Help), _) => {
+                    show_help = true;
+                    None
+                }
+                (Some(Command::Quit), _) => {
                     break;
                 }
-                Key::Down => {
+                (Some(Command::Down), _) if view == View::Activities => {
                     stat_activity_view.next();
+                    None
                 }
-                Key::Up => {
+                (Some(Command::Up), _) if view == View::Activities => {
                     stat_activity_view.previous();
+                    None
+                }
+                (Some(Command::PageDown), _) if view == View::Activities => {
+                    stat_activity_view.page_down();
+                    None
+                }
+                (Some(Command::PageUp), _) if view == View::Activities => {
+                    stat_activity_view.page_up();
+                    None
+                }
+                (Some(Command::First), _) if view == View::Activities => {
+                    stat_activity_view.first();
+                    None
+                }
+                (Some(Command::Last), _) if view == View::Activities => {
+                    stat_activity_view.last();
+                    None
+                }
+                (Some(Command::ScrollLeft), _) if view == View::Activities => {
+                    stat_activity_view.scroll_left();
+                    None
+                }
+                (Some(Command::ScrollRight), _) if view == View::Activities => {
+                    stat_activity_view.scroll_right(&layouts.activities);
+                    None
+                }
+                (Some(Command::JumpToPid), _) if view == View::Activities => {
+                    prompt = Some((Prompt::Pid, String::new()));
+                    None
                 }
-                Key::Ctrl('r') => {
-                    stat_activity_view.activities = get_activities(&ctx.client).await?;
-                    system_info = get_system_info(&ctx.client).await?;
+                (Some(Command::Filter), _) if view == View::Activities => {
+                    let filter = stat_activity_view.filter.clone().unwrap_or_default();
+                    prompt = Some((Prompt::Filter, filter));
+                    None
                 }
-                _ => {}
+                (Some(Command::Explain), _) if view == View::Activities => {
+                    match stat_activity_view.selected().cloned() {
+                        Some(activity) => {
+                            match source.explain(servers_view.selected, &activity).await {
+                                Ok((mode, plan)) => {
+                                    plan_view = Some(PlanView {
+                                        activity,
+                                        mode,
+                                        plan,
+                                        scroll: 0,
+                                        confirm_analyze: false,
+                                        error: None,
+                                    });
+                                }
+                                Err(err) => {
+                                    message = Some(format!("explain {}: {}", activity.pid, err))
+                                }
+                            }
+                        }
+                        None => message = Some(String::from("no backend selected")),
+                    }
+                    None
+                }
+                (Some(Command::Copy), _) if view == View::Activities => {
+                    copying = stat_activity_view.selected().cloned();
+                    if copying.is_none() {
+                        message = Some(String::from("no backend selected"));
+                    }
+                    None
+                }
+                (Some(Command::GroupBy), _) if view == View::Activities => {
+                    stat_activity_view.cycle_grouping(servers_view.activities());
+                    None
+                }
+                (Some(Command::Expand), _) if view == View::Activities => {
+                    stat_activity_view.toggle_expanded(servers_view.activities());
+                    None
+                }
+                (Some(Command::WaitEvents), _) => {
+                    show_waits = !show_waits;
+                    None
+                }
+                (Some(Command::History), _) => {
+                    history_view = Some(HistoryView {
+                        window: history.default_window(),
+                        before: Duration::zero(),
+                    });
+                    None
+                }
+                (Some(Command::Tag), _) if view == View::Activities => {
+                    stat_activity_view.toggle_tag();
+                    None
+                }
+                (Some(Command::TagAll), _) if view == View::Activities => {
+                    stat_activity_view.tag_all();
+                    None
+                }
+                (Some(Command::UntagAll), _) if view == View::Activities => {
+                    stat_activity_view.tagged.clear();
+                    None
+                }
+                (Some(Command::NextServer), _) => {
+                    servers_view.next();
+                    stat_activity_view.reset(servers_view.activities());
+                    None
+                }
+                (Some(Command::PreviousServer), _) => {
+                    servers_view.previous();
+                    stat_activity_view.reset(servers_view.activities());
+                    None
+                }
+                (Some(Command::ToggleTables), _) => {
+                    view = if view == View::Tables {
+                        View::Activities
+                    } else {
+                        View::Tables
+                    };
+                    Some(source.fetch(view == View::Tables).await?)
+                }
+                (Some(Command::Setup), _) => {
+                    let layout = match view {
+                        View::Activities => &layouts.activities,
+                        View::Tables => &layouts.tables,
+                    };
+                    setup = Some((0, layout.clone()));
+                    None
+                }
+                (Some(Command::Refresh), _) => Some(source.fetch(view == View::Tables).await?),
+                (Some(command @ Command::Cancel), _) | (Some(command @ Command::Terminate), _)
+                    if view == View::Activities =>
+                {
+                    let action = if command == Command::Cancel {
+                        Action::Cancel
+                    } else {
+                        Action::Terminate
+                    };
+                    let targets = stat_activity_view.targets();
+                    message = Some(match targets.as_slice() {
+                        _ if source.read_only() => {
+                            format!("{}: {}", action, CliError::ReadOnly)
+                        }
+                        [] => String::from("no backend selected"),
+                        [activity] => {
+                            let prompt =
+                                format!("{} backend {}? [y] confirm", action, activity.pid);
+                            pending = Some((action, targets));
+                            prompt
+                        }
+                        _ => {
+                            let prompt = format!(
+                                "{} the {} tagged backends? [y] confirm",
+                                action,
+                                targets.len()
+                            );
+                            pending = Some((action, targets));
+                            prompt
+                        }
+                    });
+                    None
+                }
+                (Some(Command::PlayPause), Source::Replay(replay)) => {
+                    replay.toggle_pause();
+                    None
+                }
+                (Some(Command::StepForward), Source::Replay(replay)) => {
+                    replay.seek(1);
+                    Some(replay.statuses())
+                }
+                (Some(Command::StepBackward), Source::Replay(replay)) => {
+                    replay.seek(-1);
+                    Some(replay.statuses())
+                }
+                (Some(Command::SeekForward), Source::Replay(replay)) => {
+                    replay.seek(REPLAY_SEEK_STEP);
+                    Some(replay.statuses())
+                }
+                (Some(Command::SeekBackward), Source::Replay(replay)) => {
+                    replay.seek(-REPLAY_SEEK_STEP);
+                    Some(replay.statuses())
+                }
+                (Some(Command::SeekStart), Source::Replay(replay)) => {
+                    replay.seek_start();
+                    Some(replay.statuses())
+                }
+                (Some(Command::SeekEnd), Source::Replay(replay)) => {
+                    replay.seek_end();
+                    Some(replay.statuses())
+                }
+                _ => None,
             },
-            Event::Tick => {
-                stat_activity_view.activities = get_activities(&ctx.client).await?;
-                system_info = get_system_info(&ctx.client).await?;
+            Event::Tick => Some(source.tick(view == View::Tables).await?),
+            Event::Sample => {
+                for (server, sampled_at, activities) in source.sample().await {
+                    history.record(&server, sampled_at, &activities);
+                }
+                events.sampled();
+                None
+            }
+        };
+        if let Some(statuses) = statuses {
+            firing = alerts.update(&statuses, hooks);
+            if replaying {
+                history.record_statuses(&statuses);
             }
+            servers_view.update(statuses);
+            stat_activity_view.update(servers_view.activities());
         }
     }
     terminal.clear()?;
@@ -0,0 +1,46 @@
+use std::collections::HashMap;
+use std::hash::Hash;
+
+use crate::db::PGStatActivity;
+
+/// Class of the active backends which are not waiting, i.e. running on a CPU or waiting for
+/// one, as active session history tools call it.
+const CPU: &str = "CPU";
+
+/// Class of the wait event of the active backend, see `CPU`.
+pub fn wait_class(activity: &PGStatActivity) -> &str {
+    activity.wait_event_type.as_deref().unwrap_or(CPU)
+}
+
+/// Wait event of the backend along with its class, e.g. `Lock: transactionid`, if waiting.
+pub fn format_wait_event(activity: &PGStatActivity) -> Option<String> {
+    match (&activity.wait_event_type, &activity.wait_event) {
+        (Some(class), Some(event)) => Some(format!("{}: {}", class, event)),
+        (Some(class), None) => Some(class.clone()),
+        _ => None,
+    }
+}
+
+/// Wait event of the active backend, see `CPU`.
+pub fn wait_label(activity: &PGStatActivity) -> String {
+    format_wait_event(activity).unwrap_or_else(|| String::from(CPU))
+}
+
+pub fn is_active(activity: &PGStatActivity) -> bool {
+    activity.state.as_deref() == Some("active")
+}
+
+/// Counts the occurrences of each label, the most frequent first.
+pub fn count_by<K, I>(labels: I) -> Vec<(K, usize)>
+where
+    I: IntoIterator<Item = K>,
+    K: Hash + Ord,
+{
+    let mut counts: HashMap<K, usize> = HashMap::new();
+    for label in labels {
+        *counts.entry(label).or_default() += 1;
+    }
+    let mut counts = counts.into_iter().collect::<Vec<_>>();
+    counts.sort_by(|(a, a_count), (b, b_count)| b_count.cmp(a_count).then_with(|| a.cmp(b)));
+    counts
+}
@@ -0,0 +1,373 @@
+use std::collections::{HashSet, VecDeque};
+use std::time::{Duration as StdDuration, Instant};
+
+use chrono::{DateTime, Duration, Utc};
+use config::ConfigError;
+
+use crate::alert::{parse_condition, Metric, Threshold};
+use crate::audit::{Action, AuditLog, REQUESTED};
+use crate::db::{
+    get_backend_pid, get_protected_roles, terminate_unchanged_backend, PGStatActivity,
+};
+use crate::server::Server;
+use crate::{CliResult, Context};
+
+/// Window of the per-rule rate limits.
+const RATE_LIMIT_WINDOW: StdDuration = StdDuration::from_secs(60);
+/// Terminations allowed per rule and minute unless configured otherwise.
+const DEFAULT_RATE_LIMIT: usize = 10;
+
+/// Watchdog section of the configuration file.
+#[derive(Debug, Default, Deserialize)]
+pub(crate) struct WatchdogSettings {
+    /// Logs the backends which would be terminated without terminating them.
+    #[serde(default)]
+    dry_run: bool,
+    /// Roles never terminated, on top of superusers and replication roles.
+    #[serde(default)]
+    exclude_roles: Vec<String>,
+    #[serde(default)]
+    rules: Vec<TerminationRuleSettings>,
+}
+
+/// Termination rule as written in the configuration file.
+#[derive(Debug, Deserialize)]
+struct TerminationRuleSettings {
+    /// Defaults to the rule itself.
+    name: Option<String>,
+    /// Condition on a per-backend metric, e.g. `idle in transaction > 10m`.
+    rule: String,
+    /// Restricts the rule to the backends of a role.
+    user: Option<String>,
+    /// Restricts the rule to the backends of an application, as in `application_name`.
+    application: Option<String>,
+    /// Restricts the rule to the backends connected to a database.
+    database: Option<String>,
+    /// Maximum number of backends terminated by the rule within a minute.
+    rate_limit: Option<usize>,
+}
+
+/// A rule terminating the backends it matches.
+struct TerminationRule {
+    name: String,
+    metric: Metric,
+    threshold: Duration,
+    user: Option<String>,
+    application: Option<String>,
+    database: Option<String>,
+    rate_limit: usize,
+    /// When the last terminations happened, within the rate limit window.
+    terminations: VecDeque<Instant>,
+}
+
+impl TerminationRule {
+    fn new(settings: &TerminationRuleSettings) -> Result<TerminationRule, ConfigError> {
+        let (metric, threshold) = parse_condition(&settings.rule)?;
+        let threshold = match threshold {
+            Threshold::Duration(threshold) if metric.is_per_backend() => threshold,
+            _ => {
+                return Err(ConfigError::Message(format!(
+                    "invalid termination rule '{}': only backend durations can be used",
+                    settings.rule
+                )))
+            }
+        };
+        Ok(TerminationRule {
+            name: settings
+                .name
+                .clone()
+                .unwrap_or_else(|| settings.rule.clone()),
+            metric,
+            threshold,
+            user: settings.user.clone(),
+            application: settings.application.clone(),
+            database: settings.database.clone(),
+            rate_limit: settings.rate_limit.unwrap_or(DEFAULT_RATE_LIMIT),
+            terminations: VecDeque::new(),
+        })
+    }
+
+    fn matches(&self, activity: &PGStatActivity) -> bool {
+        let filter = |expected: &Option<String>, actual: &Option<String>| {
+            expected.is_none() || expected == actual
+        };
+        filter(&self.user, &activity.usename)
+            && filter(&self.application, &activity.application_name)
+            && filter(&self.database, &activity.datname)
+            && matches!(self.metric.backend_duration(activity), Some(duration) if duration > self.threshold)
+    }
+
+    /// Whether the rate limit allows another termination, only the successful ones counting.
+    fn allows(&mut self, now: Instant) -> bool {
+        while matches!(self.terminations.front(), Some(at) if now.duration_since(*at) >= RATE_LIMIT_WINDOW)
+        {
+            self.terminations.pop_front();
+        }
+        self.terminations.len() < self.rate_limit
+    }
+
+    /// Counts a backend terminated by the rule against its rate limit.
+    fn count(&mut self, now: Instant) {
+        self.terminations.push_back(now);
+    }
+}
+
+/// Whether the backend is spared by every rule, as the backends of protected roles and pgtop's
+/// own are.
+fn is_protected(activity: &PGStatActivity, protected: &[String], own_pid: i32) -> bool {
+    // backends of hidden roles cannot be told apart from protected ones
+    let eligible = matches!(&activity.usename, Some(user) if !protected.contains(user));
+    !eligible || activity.pid == own_pid
+}
+
+/// Terminates the backends matching the termination rules of the configuration file.
+pub struct Watchdog {
+    rules: Vec<TerminationRule>,
+    exclude_roles: Vec<String>,
+    dry_run: bool,
+    /// Backends already reported by a dry run, by server, pid and start.
+    reported: HashSet<(String, i32, DateTime<Utc>)>,
+}
+
+impl Watchdog {
+    /// Validates the rules, a dry run being forced by either the configuration or `dry_run`.
+    pub fn new(settings: &WatchdogSettings, dry_run: bool) -> Result<Watchdog, ConfigError> {
+        let rules = settings
+            .rules
+            .iter()
+            .map(TerminationRule::new)
+            .collect::<Result<Vec<_>, _>>()?;
+        if rules.is_empty() {
+            return Err(ConfigError::Message(String::from(
+                "the watchdog requires at least one rule under watchdog.rules",
+            )));
+        }
+        Ok(Watchdog {
+            rules,
+            exclude_roles: settings.exclude_roles.clone(),
+            dry_run: dry_run || settings.dry_run,
+            reported: HashSet::new(),
+        })
+    }
+
+    /// Applies the rules to the backends of the server, each action being logged.
+    async fn check(&mut self, server: &mut Server, audit: &mut AuditLog) -> CliResult<()> {
+        let snapshot = server.snapshot(false).await?;
+        let role = server.role().map(String::from);
+        let (client, _) = server.connect().await?;
+        let mut protected = get_protected_roles(client).await?;
+        protected.extend(self.exclude_roles.iter().cloned());
+        let own_pid = get_backend_pid(client).await?;
+
+        let now = Instant::now();
+        let mut handled = HashSet::new();
+        let mut reported = HashSet::new();
+        for rule in self.rules.iter_mut() {
+            let mut spared = 0;
+            for activity in &snapshot.activities {
+                if is_protected(activity, &protected, own_pid)
+                    || handled.contains(&activity.pid)
+                    || !rule.matches(activity)
+                {
+                    continue;
+                }
+                handled.insert(activity.pid);
+                let backend = (
+                    snapshot.server.clone(),
+                    activity.pid,
+                    activity.backend_start.unwrap_or(snapshot.timestamp),
+                );
+                if self.dry_run {
+                    // a backend is reported once rather than at every refresh
+                    if !self.reported.contains(&backend) {
+                        audit.record(
+                            &snapshot.server,
+                            role.as_deref(),
+                            Action::Terminate,
+                            activity,
+                            Some(&rule.name),
+                            "dry run",
+                        )?;
+                    }
+                    reported.insert(backend);
+                    continue;
+                }
+                if !rule.allows(now) {
+                    spared += 1;
+                    continue;
+                }
+                audit.record(
+                    &snapshot.server,
+                    role.as_deref(),
+                    Action::Terminate,
+                    activity,
+                    Some(&rule.name),
+                    REQUESTED,
+                )?;
+                // the backend may have moved on since the snapshot, it is only terminated if
+                // the rule still holds
+                let terminated = terminate_unchanged_backend(client, activity).await;
+                if let Ok(Some(true)) = terminated {
+                    rule.count(now);
+                }
+                let result = match terminated {
+                    Ok(None) => String::from("skipped: backend gone or no longer matching"),
+                    result => Action::Terminate.describe(&result),
+                };
+                audit.record(
+                    &snapshot.server,
+                    role.as_deref(),
+                    Action::Terminate,
+                    activity,
+                    Some(&rule.name),
+                    &result,
+                )?;
+            }
+            if spared > 0 {
+                eprintln!(
+                    "{}: rate limit of rule '{}' reached, {} backends spared",
+                    snapshot.server, rule.name, spared
+                );
+            }
+        }
+        if self.dry_run {
+            self.reported
+                .retain(|(server, ..)| server != &snapshot.server);
+            self.reported.extend(reported);
+        }
+        Ok(())
+    }
+}
+
+/// Checks the servers at every refresh until interrupted. A server failing to be checked is
+/// reported and checked again at the next refresh.
+pub async fn run(mut ctx: Context, mut watchdog: Watchdog, mut audit: AuditLog) -> CliResult<()> {
+    loop {
+        for server in ctx.servers.iter_mut() {
+            if let Err(err) = watchdog.check(server, &mut audit).await {
+                eprintln!("{}: {}", server.name, err);
+            }
+        }
+        tokio::time::sleep(ctx.refresh_rate).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration as StdDuration, Instant};
+
+    use chrono::{Duration, TimeZone, Utc};
+
+    use super::{is_protected, TerminationRule, TerminationRuleSettings};
+    use crate::db::PGStatActivity;
+
+    fn activity(pid: i32, user: &str, state: &str, idle: Duration) -> PGStatActivity {
+        let sampled_at = Utc.with_ymd_and_hms(2024, 1, 1, 12, 0, 0).unwrap();
+        PGStatActivity {
+            datname: Some(String::from("shop")),
+            pid,
+            usename: Some(String::from(user)),
+            application_name: Some(String::from("billing")),
+            client_addr: None,
+            client_port: Some(-1),
+            backend_start: Some(sampled_at - Duration::hours(1)),
+            xact_start: Some(sampled_at - idle),
+            query_start: Some(sampled_at - idle),
+            state_change: Some(sampled_at - idle),
+            sampled_at,
+            state: Some(String::from(state)),
+            wait_event_type: None,
+            wait_event: None,
+            query: Some(String::from("select 1")),
+            blocked_by: vec![],
+        }
+    }
+
+    fn rule(rule: &str, user: Option<&str>, rate_limit: Option<usize>) -> TerminationRule {
+        TerminationRule::new(&TerminationRuleSettings {
+            name: None,
+            rule: String::from(rule),
+            user: user.map(String::from),
+            application: Some(String::from("billing")),
+            database: None,
+            rate_limit,
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn rules_match_the_backends_over_the_threshold() {
+        let rule = rule("idle in transaction > 5m", Some("app"), None);
+        let idle = "idle in transaction";
+        assert!(rule.matches(&activity(1, "app", idle, Duration::minutes(6))));
+        assert!(!rule.matches(&activity(1, "app", idle, Duration::minutes(5))));
+        assert!(!rule.matches(&activity(1, "app", "active", Duration::minutes(6))));
+        assert!(!rule.matches(&activity(1, "batch", idle, Duration::minutes(6))));
+        let mut other = activity(1, "app", idle, Duration::minutes(6));
+        other.application_name = Some(String::from("psql"));
+        assert!(!rule.matches(&other));
+    }
+
+    #[test]
+    fn rules_only_watch_backend_durations() {
+        for condition in &["connections > 10", "replication lag > 1GB", "idle > 5m"] {
+            let settings = TerminationRuleSettings {
+                name: None,
+                rule: condition.to_string(),
+                user: None,
+                application: None,
+                database: None,
+                rate_limit: None,
+            };
+            assert!(TerminationRule::new(&settings).is_err(), "{}", condition);
+        }
+    }
+
+    #[test]
+    fn protected_roles_and_pgtop_are_spared() {
+        // superusers and replication roles come from the server, on top of exclude_roles
+        let protected = vec![String::from("postgres"), String::from("replicator")];
+        let idle = Duration::minutes(10);
+        assert!(!is_protected(
+            &activity(1, "app", "idle", idle),
+            &protected,
+            99
+        ));
+        assert!(is_protected(
+            &activity(1, "postgres", "idle", idle),
+            &protected,
+            99
+        ));
+        assert!(is_protected(
+            &activity(1, "replicator", "idle", idle),
+            &protected,
+            99
+        ));
+        assert!(is_protected(
+            &activity(99, "app", "idle", idle),
+            &protected,
+            99
+        ));
+        let mut hidden = activity(1, "app", "idle", idle);
+        hidden.usename = None;
+        assert!(is_protected(&hidden, &protected, 99));
+    }
+
+    #[test]
+    fn rate_limit_counts_terminations_within_a_minute() {
+        let mut rule = rule("query duration > 1m", None, Some(2));
+        let start = Instant::now();
+        assert!(rule.allows(start));
+        // failed or skipped terminations are not counted
+        assert!(rule.allows(start));
+        rule.count(start);
+        assert!(rule.allows(start));
+        rule.count(start + StdDuration::from_secs(30));
+        assert!(!rule.allows(start + StdDuration::from_secs(30)));
+        assert!(!rule.allows(start + StdDuration::from_secs(59)));
+        assert!(rule.allows(start + StdDuration::from_secs(60)));
+        rule.count(start + StdDuration::from_secs(60));
+        assert!(!rule.allows(start + StdDuration::from_secs(89)));
+        assert!(rule.allows(start + StdDuration::from_secs(90)));
+    }
+}